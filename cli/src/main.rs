@@ -1,18 +1,65 @@
 mod opts;
 
-use std::{
-    io::{Read, Write},
-    os::unix::net::UnixStream,
-    path::PathBuf,
-    time::Duration,
-};
+use std::{collections::BTreeMap, io::ErrorKind, path::PathBuf, process::exit, time::Duration};
 
 use clap::Parser;
 use serde::Serialize;
-use wpaperd_ipc::{socket_path, IpcError, IpcMessage, IpcResponse};
+use wpaperd_ipc::{
+    current_instance, instance_socket_path, Client, ClientError, ConfigDiagnostic, DisplayDetails,
+    GotoTarget, ImagePosition, IpcError, IpcEvent, IpcMessage, IpcResponse, WallpaperOverrides,
+    WallpaperSource,
+};
 
 use crate::opts::{Opts, SubCmd};
 
+// Exit codes, so that scripts can tell the failures apart. 2 is used by clap for invalid
+// arguments.
+const EXIT_FAILURE: i32 = 1;
+const EXIT_NOT_RUNNING: i32 = 3;
+const EXIT_MONITOR_NOT_FOUND: i32 = 4;
+const EXIT_PROTOCOL_ERROR: i32 = 5;
+
+/// Print a human-readable diagnostic for the error and exit with the matching code.
+/// `location` describes where wpaperd was expected to run.
+fn fail(err: ClientError, location: &str, timeout: Duration) -> ! {
+    let code = match &err {
+        ClientError::Connect { source, .. }
+            if matches!(
+                source.kind(),
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused
+            ) =>
+        {
+            eprintln!("wpaperd is not running on {location}");
+            EXIT_NOT_RUNNING
+        }
+        ClientError::Io(source)
+            if matches!(source.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+        {
+            eprintln!(
+                "wpaperd did not answer within {}",
+                humantime::format_duration(timeout)
+            );
+            EXIT_PROTOCOL_ERROR
+        }
+        ClientError::Daemon(IpcError::MonitorNotFound { .. }) => {
+            eprintln!("{err}");
+            EXIT_MONITOR_NOT_FOUND
+        }
+        ClientError::Io(_)
+        | ClientError::Disconnected
+        | ClientError::VersionMismatch { .. }
+        | ClientError::UnexpectedResponse => {
+            eprintln!("{err}");
+            EXIT_PROTOCOL_ERROR
+        }
+        ClientError::SocketPath(_) | ClientError::Connect { .. } | ClientError::Daemon(_) => {
+            eprintln!("{err}");
+            EXIT_FAILURE
+        }
+    };
+    exit(code)
+}
+
 fn unquote(s: String) -> String {
     if s.starts_with('"') && s.ends_with('"') {
         s.trim_start_matches('"').trim_end_matches('"').to_string()
@@ -21,13 +68,398 @@ fn unquote(s: String) -> String {
     }
 }
 
+/// List the overridden settings with their value
+fn overrides_entries(overrides: &WallpaperOverrides) -> Vec<(&'static str, String)> {
+    let mut entries = Vec::new();
+    if let Some(duration) = overrides.duration {
+        entries.push(("duration", humantime::format_duration(duration).to_string()));
+    }
+    if let Some(mode) = &overrides.mode {
+        entries.push(("mode", mode.clone()));
+    }
+    if let Some(offset) = overrides.offset {
+        entries.push(("offset", offset.to_string()));
+    }
+    if let Some(transition) = &overrides.transition {
+        entries.push(("transition", transition.clone()));
+    }
+    if let Some(sorting) = &overrides.sorting {
+        entries.push(("sorting", sorting.clone()));
+    }
+    entries
+}
+
+fn print_displays(displays: Vec<DisplayDetails>, json: bool) {
+    if json {
+        #[derive(Serialize)]
+        struct Wallpaper {
+            path: Vec<WallpaperSource>,
+            mode: String,
+            sorting: Option<String>,
+            #[serde(with = "humantime_serde")]
+            duration: Option<Duration>,
+            transition: String,
+            transition_time: u32,
+            group: Option<u8>,
+            offset: Option<f32>,
+            queue_size: usize,
+            recursive: bool,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            include: Vec<String>,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            exclude: Vec<String>,
+            exec: Option<PathBuf>,
+        }
+        #[derive(Serialize)]
+        struct Item {
+            display: String,
+            description: String,
+            make: String,
+            model: String,
+            serial: String,
+            width: i32,
+            height: i32,
+            scale: i32,
+            transform: String,
+            section: String,
+            wallpaper: Wallpaper,
+            #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+            overrides: BTreeMap<&'static str, String>,
+            current_image: PathBuf,
+            paused: bool,
+            has_context: bool,
+        }
+        let val = displays
+            .into_iter()
+            .map(|display| Item {
+                overrides: overrides_entries(&display.overrides).into_iter().collect(),
+                display: display.name,
+                description: display.description,
+                make: display.make,
+                model: display.model,
+                serial: display.serial,
+                width: display.width,
+                height: display.height,
+                scale: display.scale,
+                transform: display.transform,
+                section: display.section,
+                wallpaper: Wallpaper {
+                    path: display.wallpaper.path,
+                    mode: display.wallpaper.mode,
+                    sorting: display.wallpaper.sorting,
+                    duration: display.wallpaper.duration,
+                    transition: display.wallpaper.transition,
+                    transition_time: display.wallpaper.transition_time,
+                    group: display.wallpaper.group,
+                    offset: display.wallpaper.offset,
+                    queue_size: display.wallpaper.queue_size,
+                    recursive: display.wallpaper.recursive,
+                    include: display.wallpaper.include,
+                    exclude: display.wallpaper.exclude,
+                    exec: display.wallpaper.exec,
+                },
+                current_image: display.current_image,
+                paused: display.paused,
+                has_context: display.has_context,
+            })
+            .collect::<Vec<_>>();
+        println!(
+            "{}",
+            serde_json::to_string(&val).expect("json encoding to work")
+        );
+        return;
+    }
+
+    for display in displays {
+        let wallpaper = display.wallpaper;
+        println!("{}: {}", display.name, display.description);
+        println!(
+            "  make: {}, model: {}, serial: {}",
+            display.make, display.model, display.serial
+        );
+        println!(
+            "  size: {}x{}, scale: {}, transform: {}",
+            display.width, display.height, display.scale, display.transform
+        );
+        println!("  section: [{}]", display.section);
+        let paths: Vec<String> = wallpaper
+            .path
+            .iter()
+            .map(|source| match source.weight {
+                1 => source.path.to_string_lossy().into_owned(),
+                weight => format!("{} (weight {weight})", source.path.to_string_lossy()),
+            })
+            .collect();
+        println!("  path: {}", paths.join(", "));
+        let mut settings = vec![format!("mode: {}", wallpaper.mode)];
+        if let Some(sorting) = wallpaper.sorting {
+            settings.push(format!("sorting: {sorting}"));
+        }
+        if let Some(group) = wallpaper.group {
+            settings.push(format!("group: {group}"));
+        }
+        if let Some(duration) = wallpaper.duration {
+            settings.push(format!(
+                "duration: {}",
+                humantime::format_duration(duration)
+            ));
+        }
+        if let Some(offset) = wallpaper.offset {
+            settings.push(format!("offset: {offset}"));
+        }
+        settings.push(format!(
+            "transition: {} ({}ms)",
+            wallpaper.transition, wallpaper.transition_time
+        ));
+        settings.push(format!("queue-size: {}", wallpaper.queue_size));
+        settings.push(format!("recursive: {}", wallpaper.recursive));
+        println!("  {}", settings.join(", "));
+        if !wallpaper.include.is_empty() {
+            println!("  include: {}", wallpaper.include.join(", "));
+        }
+        if !wallpaper.exclude.is_empty() {
+            println!("  exclude: {}", wallpaper.exclude.join(", "));
+        }
+        if let Some(exec) = wallpaper.exec {
+            println!("  exec: {}", exec.to_string_lossy());
+        }
+        let overrides = overrides_entries(&display.overrides);
+        if !overrides.is_empty() {
+            println!(
+                "  overrides: {}",
+                overrides
+                    .into_iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+        }
+        println!(
+            "  current image: {}",
+            display.current_image.to_string_lossy()
+        );
+        println!(
+            "  paused: {}, egl context: {}",
+            display.paused, display.has_context
+        );
+    }
+}
+
+fn print_images(files: Vec<PathBuf>, position: ImagePosition, json: bool) {
+    if json {
+        #[derive(Serialize)]
+        struct Item {
+            files: Vec<PathBuf>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            index: Option<usize>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            queue: Option<Vec<PathBuf>>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            queue_index: Option<usize>,
+        }
+        let (index, queue, queue_index) = match position {
+            ImagePosition::Index(index) => (Some(index), None, None),
+            ImagePosition::Queue { entries, current } => (None, Some(entries), Some(current)),
+            ImagePosition::Static => (None, None, None),
+        };
+        println!(
+            "{}",
+            serde_json::to_string(&Item {
+                files,
+                index,
+                queue,
+                queue_index
+            })
+            .expect("json encoding to work")
+        );
+        return;
+    }
+
+    fn print_list(list: &[PathBuf], current: Option<usize>) {
+        for (i, path) in list.iter().enumerate() {
+            let marker = if Some(i) == current { '>' } else { ' ' };
+            println!("{marker} {}", path.to_string_lossy());
+        }
+    }
+
+    println!("files ({}):", files.len());
+    match position {
+        ImagePosition::Index(index) => print_list(&files, Some(index)),
+        ImagePosition::Queue { entries, current } => {
+            print_list(&files, None);
+            println!("queue ({}):", entries.len());
+            print_list(&entries, Some(current));
+        }
+        ImagePosition::Static => print_list(&files, Some(0)),
+    }
+}
+
+fn print_version(daemon: String, protocol: u32, features: Vec<String>, json: bool) {
+    let wpaperctl = env!("CARGO_PKG_VERSION");
+    if json {
+        #[derive(Serialize)]
+        struct Item {
+            wpaperd: String,
+            wpaperctl: &'static str,
+            protocol: u32,
+            features: Vec<String>,
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&Item {
+                wpaperd: daemon,
+                wpaperctl,
+                protocol,
+                features
+            })
+            .expect("json encoding to work")
+        );
+        return;
+    }
+
+    println!("wpaperd {daemon}");
+    println!("wpaperctl {wpaperctl}");
+    println!("protocol {protocol}");
+    println!(
+        "features: {}",
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
+    );
+}
+
+fn print_config_diagnostics(
+    errors: Vec<ConfigDiagnostic>,
+    warnings: Vec<ConfigDiagnostic>,
+    json: bool,
+) {
+    if json {
+        #[derive(Serialize)]
+        struct Item {
+            errors: Vec<ConfigDiagnostic>,
+            warnings: Vec<ConfigDiagnostic>,
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&Item { errors, warnings }).expect("json encoding to work")
+        );
+        return;
+    }
+
+    for diagnostic in &errors {
+        eprintln!("error in [{}]: {}", diagnostic.section, diagnostic.message);
+    }
+    for diagnostic in &warnings {
+        eprintln!(
+            "warning in [{}]: {}",
+            diagnostic.section, diagnostic.message
+        );
+    }
+}
+
+fn print_history(entries: Vec<PathBuf>, current: usize, json: bool) {
+    if json {
+        #[derive(Serialize)]
+        struct Item {
+            entries: Vec<PathBuf>,
+            current: usize,
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&Item { entries, current }).expect("json encoding to work")
+        );
+        return;
+    }
+
+    for (i, path) in entries.iter().enumerate() {
+        let marker = if i == current { '>' } else { ' ' };
+        println!("{marker} {i}: {}", path.to_string_lossy());
+    }
+}
+
+fn print_event(event: IpcEvent, json: bool) {
+    let (event, display, path) = match event {
+        IpcEvent::WallpaperChanged { monitor, path } => {
+            ("wallpaper-changed", Some(monitor), Some(path))
+        }
+        IpcEvent::Paused { monitor } => ("paused", Some(monitor), None),
+        IpcEvent::Resumed { monitor } => ("resumed", Some(monitor), None),
+        IpcEvent::DisplayAdded { monitor } => ("display-added", Some(monitor), None),
+        IpcEvent::DisplayRemoved { monitor } => ("display-removed", Some(monitor), None),
+        IpcEvent::ConfigReloaded => ("config-reloaded", None, None),
+        IpcEvent::ImageLoadFailed { monitor, path } => {
+            ("image-load-failed", Some(monitor), Some(path))
+        }
+    };
+    if json {
+        #[derive(Serialize)]
+        struct Item {
+            event: &'static str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            display: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            path: Option<PathBuf>,
+        }
+        println!(
+            "{}",
+            serde_json::to_string(&Item {
+                event,
+                display,
+                path
+            })
+            .expect("json encoding to work")
+        );
+    } else {
+        let mut line = event.to_string();
+        if let Some(display) = display {
+            line.push(' ');
+            line.push_str(&display);
+        }
+        if let Some(path) = path {
+            line.push(' ');
+            line.push_str(&path.to_string_lossy());
+        }
+        println!("{line}");
+    }
+}
+
 fn main() {
     let args = Opts::parse();
 
     let mut json_resp = false;
 
-    let mut conn = UnixStream::connect(socket_path().unwrap()).unwrap();
+    let timeout = args.timeout;
+    let (socket, location) = match args.socket {
+        Some(socket) => {
+            let location = socket.to_string_lossy().into_owned();
+            (socket, location)
+        }
+        None => {
+            let instance = args.instance.unwrap_or_else(current_instance);
+            let socket = instance_socket_path(&instance)
+                .unwrap_or_else(|err| fail(ClientError::SocketPath(err), &instance, timeout));
+            (socket, instance)
+        }
+    };
+    let mut client =
+        Client::connect_to(&socket, timeout).unwrap_or_else(|err| fail(err, &location, timeout));
+
     let msg = match args.subcmd {
+        SubCmd::Ping => IpcMessage::Ping,
+        SubCmd::Version { json } => {
+            json_resp = json;
+            IpcMessage::Version
+        }
+        SubCmd::Quit => IpcMessage::Shutdown,
+        SubCmd::ReloadConfig { json, path } => {
+            json_resp = json;
+            IpcMessage::ReloadConfig {
+                // wpaperd runs in a different working directory
+                path: path.map(|path| path.canonicalize().unwrap_or(path)),
+            }
+        }
         SubCmd::GetWallpaper { monitor } => IpcMessage::CurrentWallpaper {
             monitor: unquote(monitor),
         },
@@ -59,14 +491,94 @@ fn main() {
                 monitors: monitors.into_iter().map(unquote).collect(),
             }
         }
+        SubCmd::SetWallpaper {
+            path,
+            monitors,
+            sticky,
+        } => IpcMessage::SetWallpaper {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            // wpaperd runs in a different working directory
+            path: path.canonicalize().unwrap_or(path),
+            sticky,
+        },
+        SubCmd::GotoImage {
+            target,
+            monitors,
+            name,
+        } => IpcMessage::GotoImage {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            target: match target.parse() {
+                Ok(index) if !name => GotoTarget::Index(index),
+                _ => GotoTarget::Name(target),
+            },
+        },
+        SubCmd::UnsetWallpaper { monitors } => IpcMessage::UnsetWallpaper {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::SetOverrides {
+            monitors,
+            duration,
+            mode,
+            offset,
+            transition,
+            sorting,
+            until_reload,
+        } => IpcMessage::SetOverrides {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            overrides: WallpaperOverrides {
+                duration,
+                mode,
+                offset,
+                transition,
+                sorting,
+            },
+            until_reload,
+        },
+        SubCmd::ClearOverrides { monitors } => IpcMessage::ClearOverrides {
+            monitors: monitors.into_iter().map(unquote).collect(),
+        },
+        SubCmd::ListDisplays { json, monitors } => {
+            json_resp = json;
+            IpcMessage::ListDisplays {
+                monitors: monitors.into_iter().map(unquote).collect(),
+            }
+        }
+        SubCmd::ListImages { json, monitor } => {
+            json_resp = json;
+            IpcMessage::ListImages {
+                monitor: unquote(monitor),
+            }
+        }
+        SubCmd::History { json, monitor } => {
+            json_resp = json;
+            IpcMessage::GetHistory {
+                monitor: unquote(monitor),
+            }
+        }
+        SubCmd::JumpToHistory { monitor, index } => IpcMessage::JumpToHistory {
+            monitor: unquote(monitor),
+            index,
+        },
+        SubCmd::Subscribe { json } => {
+            json_resp = json;
+            IpcMessage::Subscribe
+        }
     };
 
-    conn.write_all(&serde_json::to_vec(&msg).unwrap()).unwrap();
-    let mut buf = String::new();
-    conn.read_to_string(&mut buf).unwrap();
-    let res: Result<IpcResponse, IpcError> =
-        serde_json::from_str(&buf).expect("wpaperd to return a valid json");
-    match res {
+    if let IpcMessage::Subscribe = msg {
+        let subscription = client
+            .subscribe()
+            .unwrap_or_else(|err| fail(err, &location, timeout));
+        for event in subscription {
+            match event {
+                Ok(event) => print_event(event, json_resp),
+                Err(err) => fail(err, &location, timeout),
+            }
+        }
+        return;
+    }
+
+    match client.request(&msg) {
         Ok(resp) => match resp {
             IpcResponse::CurrentWallpaper { path } => println!("{}", path.to_string_lossy()),
             IpcResponse::AllWallpapers { entries: paths } => {
@@ -115,13 +627,16 @@ fn main() {
                         status: String,
                         #[serde(rename = "duration_left", with = "humantime_serde")]
                         duration_left: Option<Duration>,
+                        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+                        overrides: BTreeMap<&'static str, String>,
                     }
                     let val = entries
                         .into_iter()
-                        .map(|(display, status, duration_left)| Item {
-                            display,
-                            status,
-                            duration_left: duration_left.map(clean_duration),
+                        .map(|entry| Item {
+                            overrides: overrides_entries(&entry.overrides).into_iter().collect(),
+                            display: entry.display,
+                            status: entry.status,
+                            duration_left: entry.duration_left.map(clean_duration),
                         })
                         .collect::<Vec<_>>();
                     println!(
@@ -129,29 +644,64 @@ fn main() {
                         serde_json::to_string(&val).expect("json encoding to work")
                     );
                 } else {
-                    for (monitor, status, duration_left) in entries {
+                    for entry in entries {
+                        let overrides = overrides_entries(&entry.overrides);
                         println!(
-                            "{monitor}: {status}{}",
-                            if let Some(d) = duration_left {
+                            "{}: {}{}{}",
+                            entry.display,
+                            entry.status,
+                            if let Some(d) = entry.duration_left {
                                 format!(" ({} left)", humantime::format_duration(clean_duration(d)))
                             } else {
                                 "".to_string()
+                            },
+                            if overrides.is_empty() {
+                                "".to_string()
+                            } else {
+                                format!(
+                                    " [overrides: {}]",
+                                    overrides
+                                        .into_iter()
+                                        .map(|(key, value)| format!("{key}={value}"))
+                                        .collect::<Vec<_>>()
+                                        .join(" ")
+                                )
                             }
                         );
                     }
                 }
             }
-            IpcResponse::Ok => (),
-        },
-        Err(err) => match err {
-            IpcError::MonitorNotFound { monitor } => {
-                eprintln!("monitor {monitor} could not be found")
+            IpcResponse::Displays { displays } => print_displays(displays, json_resp),
+            IpcResponse::DisplayResults { results } => {
+                let mut failed = false;
+                for result in results {
+                    if let Some(error) = result.error {
+                        eprintln!("{}: {error}", result.display);
+                        failed = true;
+                    }
+                }
+                if failed {
+                    exit(EXIT_FAILURE);
+                }
             }
-            IpcError::DrawErrors(errors) => {
-                for (monitor, err) in errors {
-                    eprintln!("Wallpaper could not be drawn for monitor {monitor}: {err}")
+            IpcResponse::Pong => println!("pong"),
+            IpcResponse::ConfigReloaded { errors, warnings } => {
+                let failed = !errors.is_empty();
+                print_config_diagnostics(errors, warnings, json_resp);
+                if failed {
+                    exit(EXIT_FAILURE);
                 }
             }
+            IpcResponse::Version {
+                daemon,
+                protocol,
+                features,
+            } => print_version(daemon, protocol, features, json_resp),
+            IpcResponse::Images { files, position } => print_images(files, position, json_resp),
+            IpcResponse::History { entries, current } => print_history(entries, current, json_resp),
+            IpcResponse::Subscribed => unreachable!("handled by Client::subscribe"),
+            IpcResponse::Ok => (),
         },
+        Err(err) => fail(err, &location, timeout),
     }
 }
@@ -1,14 +1,51 @@
+use std::{path::PathBuf, time::Duration};
+
 use clap::Parser;
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit codes: 0 on success, 1 when the command failed, 2 for invalid arguments, \
+    3 when wpaperd is not running, 4 when a monitor does not exist and 5 when the communication \
+    with wpaperd failed (timeout, different protocol version)."
+)]
 pub struct Opts {
+    /// Path of the wpaperd socket, instead of the one in $XDG_RUNTIME_DIR
+    #[clap(long, global = true)]
+    pub socket: Option<PathBuf>,
+    /// Talk to the wpaperd running on this Wayland display (e.g. wayland-1), instead of the one
+    /// in $WAYLAND_DISPLAY
+    #[clap(long, global = true, conflicts_with = "socket")]
+    pub instance: Option<String>,
+    /// How long to wait for wpaperd to answer
+    #[clap(long, global = true, default_value = "5s", value_parser = humantime::parse_duration)]
+    pub timeout: Duration,
     #[clap(subcommand)]
     pub subcmd: SubCmd,
 }
 
 #[derive(clap::Subcommand)]
 pub enum SubCmd {
+    /// Check that wpaperd is running and answering
+    Ping,
+    /// Show the version of wpaperd, of its protocol and its enabled features
+    Version {
+        #[clap(short, long)]
+        json: bool,
+    },
+    /// Stop wpaperd
+    Quit,
+    /// Read the configuration again, from `path` if passed, and report the problems found.
+    /// Exit with an error if any section is not valid. Only the user running wpaperd can
+    /// pass a path; the drop-in files in conf.d are not read along it.
+    ReloadConfig {
+        #[clap(short, long)]
+        json: bool,
+        path: Option<PathBuf>,
+    },
     #[clap(visible_alias = "get")]
     GetWallpaper { monitor: String },
     #[clap(visible_alias = "get-all")]
@@ -34,4 +71,72 @@ pub enum SubCmd {
         json: bool,
         monitors: Vec<String>,
     },
+    /// Show an image on the given displays (or all of them)
+    #[clap(visible_alias = "set")]
+    SetWallpaper {
+        path: PathBuf,
+        monitors: Vec<String>,
+        /// Keep the image on screen until `unset` is called, instead of continuing the rotation
+        #[clap(short, long)]
+        sticky: bool,
+    },
+    /// Show an image of the configured directory, by index (as shown by `list-images`) or by
+    /// name, which can include its subdirectory; the rotation continues from there
+    #[clap(visible_alias = "goto")]
+    GotoImage {
+        target: String,
+        monitors: Vec<String>,
+        /// Always match the target against the image names, even if it is a number
+        #[clap(short, long)]
+        name: bool,
+    },
+    /// Stop showing the image set with `set --sticky`
+    #[clap(visible_alias = "unset")]
+    UnsetWallpaper { monitors: Vec<String> },
+    /// Change the settings of the given displays (or all of them) until they are cleared
+    #[clap(visible_alias = "override")]
+    SetOverrides {
+        monitors: Vec<String>,
+        #[clap(long, value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+        #[clap(long)]
+        mode: Option<String>,
+        #[clap(long)]
+        offset: Option<f32>,
+        #[clap(long)]
+        transition: Option<String>,
+        #[clap(long)]
+        sorting: Option<String>,
+        /// Drop these overrides the next time the configuration is reloaded
+        #[clap(long)]
+        until_reload: bool,
+    },
+    /// Go back to the configured settings of the given displays (or all of them)
+    ClearOverrides { monitors: Vec<String> },
+    /// Show the geometry, the configuration in use and the state of the displays
+    ListDisplays {
+        #[clap(short, long)]
+        json: bool,
+        monitors: Vec<String>,
+    },
+    /// Show the images available for a display and the position of the current one
+    ListImages {
+        #[clap(short, long)]
+        json: bool,
+        monitor: String,
+    },
+    /// Show the images drawn recently on a display, from the oldest to the newest
+    History {
+        #[clap(short, long)]
+        json: bool,
+        monitor: String,
+    },
+    /// Show again an image from the history, using its index in `wpaperctl history`
+    #[clap(visible_alias = "jump")]
+    JumpToHistory { monitor: String, index: usize },
+    /// Print the wpaperd events as they happen, one per line
+    Subscribe {
+        #[clap(short, long)]
+        json: bool,
+    },
 }
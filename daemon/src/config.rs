@@ -12,18 +12,24 @@ use std::{
 use color_eyre::{
     eyre::{ensure, eyre, WrapErr},
     owo_colors::OwoColorize,
-    Result, Section,
+    Report, Result, Section,
 };
 use dirs::home_dir;
 use hotwatch::{Event, Hotwatch};
 use log::{error, warn};
+use nix::unistd::{Gid, Group, User};
+use regex::Regex;
 use serde::Deserialize;
 use smithay_client_toolkit::reexports::calloop::ping::Ping;
 
 use crate::{
+    display_info::DisplayInfo,
+    filelist_cache::list_images,
     image_picker::ImagePicker,
     render::Transition,
-    wallpaper_info::{BackgroundMode, Recursive, Sorting, WallpaperInfo},
+    wallpaper_info::{
+        BackgroundMode, ImageFilter, PathSource, Recursive, Sorting, WallpaperInfo, WallpaperPath,
+    },
 };
 
 use std::os::unix::fs::PermissionsExt;
@@ -31,8 +37,9 @@ use std::os::unix::fs::PermissionsExt;
 #[derive(Default, Deserialize, PartialEq, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct SerializedWallpaperInfo {
-    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
-    pub path: Option<PathBuf>,
+    /// A single image or directory, or a list of them
+    #[serde(default, deserialize_with = "path_deserialize")]
+    pub path: Option<Vec<PathSource>>,
     #[serde(default, with = "humantime_serde")]
     pub duration: Option<Duration>,
     #[serde(rename = "apply-shadow")]
@@ -66,19 +73,72 @@ pub struct SerializedWallpaperInfo {
     /// Set as true by default
     pub recursive: Option<bool>,
 
+    /// Only use the images of the directories matching one of these globs
+    pub include: Option<Vec<String>>,
+    /// Skip the images and the subdirectories matching one of these globs
+    pub exclude: Option<Vec<String>>,
+
     // Path to bash script.
     #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
     pub exec: Option<PathBuf>,
+
+    /// Match the displays by their make, model and serial number instead of the section name
+    #[serde(rename = "match")]
+    pub output_match: Option<OutputMatch>,
+
+    /// Only used by the `re:` and `match` sections: when multiple of them match a display, the
+    /// one with the highest priority is used. Sections with the same priority are tried in
+    /// alphabetical order.
+    pub priority: Option<i32>,
+}
+
+/// Attributes that a display must have to use a section; the ones not set match any display
+#[derive(Default, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OutputMatch {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl OutputMatch {
+    fn is_empty(&self) -> bool {
+        self.make.is_none() && self.model.is_none() && self.serial.is_none()
+    }
+
+    fn matches(&self, display: &DisplayInfo) -> bool {
+        [
+            (&self.make, display.make.as_str()),
+            (&self.model, display.model.as_str()),
+            (&self.serial, display.serial()),
+        ]
+        .into_iter()
+        .all(|(expected, value)| match expected {
+            Some(expected) => expected == value,
+            None => true,
+        })
+    }
 }
 
 impl SerializedWallpaperInfo {
+    /// Expand the environment variables used in `path` and `exec`
+    fn expand_variables(&mut self) -> Result<()> {
+        for source in self.path.iter_mut().flatten() {
+            source.path = expand_variables(&source.path)?;
+        }
+        if let Some(exec) = &mut self.exec {
+            *exec = expand_variables(exec)?;
+        }
+        Ok(())
+    }
+
     pub fn apply_and_validate(&self, default: &Self) -> Result<WallpaperInfo> {
         let mut path_inherited = false;
-        let path = match (&self.path, &default.path) {
-            (Some(path), None) | (Some(path), Some(_))=> path,
-            (None, Some(path)) => {
+        let sources = match (&self.path, &default.path) {
+            (Some(sources), None) | (Some(sources), Some(_)) => sources,
+            (None, Some(sources)) => {
                 path_inherited = true;
-                path
+                sources
             }
             (None, None) => {
                 return Err(eyre!(
@@ -92,49 +152,71 @@ impl SerializedWallpaperInfo {
                     )
                 });
             }
-        }
-        .to_path_buf();
-        // Ensure that a path exists
-        if !path.exists() {
+        };
+        if sources.is_empty() {
             return Err(eyre!(
-                "Path {} for attribute {}{} must exist",
-                path.to_string_lossy().italic().yellow(),
+                "Attribute {} must contain at least one path",
                 "path".bold().italic().blue(),
-                if path_inherited {
-                    format!(
-                        " (inherited from {} configuration)",
-                        "default".magenta().bold()
-                    )
-                } else {
-                    "".to_string()
-                }
             ))
             .with_suggestion(|| {
                 format!(
-                    "Set attribute {} to an existing file or directory",
+                    "Add an image or a directory to attribute {}",
                     "path".bold().italic().blue(),
                 )
             });
         }
+        for source in sources {
+            // Ensure that a path exists
+            if !source.path.exists() {
+                return Err(eyre!(
+                    "Path {} for attribute {}{} must exist",
+                    source.path.to_string_lossy().italic().yellow(),
+                    "path".bold().italic().blue(),
+                    if path_inherited {
+                        format!(
+                            " (inherited from {} configuration)",
+                            "default".magenta().bold()
+                        )
+                    } else {
+                        "".to_string()
+                    }
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "Set attribute {} to an existing file or directory",
+                        "path".bold().italic().blue(),
+                    )
+                });
+            }
+            if source.weight == 0 {
+                return Err(eyre!(
+                    "The {} of path {} must be greater than 0",
+                    "weight".bold().italic().blue(),
+                    source.path.to_string_lossy().italic().yellow(),
+                ))
+                .with_suggestion(|| "Remove the path instead of setting its weight to 0");
+            }
+        }
+        let path = WallpaperPath::new(sources.clone());
 
         let duration = match (&self.duration, &default.duration) {
             // duration is inherited from default, but this section set path to a file, ignore
             // duration
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if path.image().is_some() && !path_inherited => None,
             (Some(duration), _) | (None, Some(duration)) => Some(*duration),
             (None, None) => None,
         };
         // duration can only be set when path is a directory
-        if duration.is_some() && !path.is_dir() {
+        if duration.is_some() && !path.is_rotating() {
             // Do no use bail! to add suggestion
             return Err(eyre!(
-                "{} cannot be set when {} points to a file",
+                "{} cannot be set when {} points to a single file",
                 "duration".bold().italic().blue(),
                 "path".bold().italic().blue(),
             )
             .with_suggestion(|| {
                 format!(
-                    "Either remove {} or set {} to a directory",
+                    "Either remove {} or set {} to a directory or a list of paths",
                     "path".bold().italic().blue(),
                     "duration".bold().italic().blue()
                 )
@@ -142,19 +224,19 @@ impl SerializedWallpaperInfo {
         }
 
         let sorting = match (&self.sorting, &default.sorting) {
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if path.image().is_some() && !path_inherited => None,
             (Some(sorting), _) | (None, Some(sorting)) => Some(*sorting),
             (None, None) => None,
         };
 
         let group = match (&self.group, &default.group) {
-            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (None, Some(_)) if path.image().is_some() && !path_inherited => None,
             (Some(sorting), _) | (None, Some(sorting)) => Some(*sorting),
             (None, None) => None,
         };
 
         // sorting and group can only be set when path is a directory
-        if (sorting.is_some() || group.is_some()) && !path.is_dir() {
+        if (sorting.is_some() || group.is_some()) && !path.is_rotating() {
             // Do no use bail! to add suggestion
             return Err(eyre!(
                 "{} cannot be set when {} is a directory",
@@ -170,7 +252,7 @@ impl SerializedWallpaperInfo {
             )
             .with_suggestion(|| {
                 format!(
-                    "Either remove {} or set {} to a directory",
+                    "Either remove {} or set {} to a directory or a list of paths",
                     if sorting.is_some() {
                         "sorting"
                     } else {
@@ -239,27 +321,59 @@ impl SerializedWallpaperInfo {
             (None, None) => None,
         };
 
+        let include = match (&self.include, &default.include) {
+            (Some(include), _) | (None, Some(include)) => include.clone(),
+            (None, None) => Vec::new(),
+        };
+        let exclude = match (&self.exclude, &default.exclude) {
+            (Some(exclude), _) | (None, Some(exclude)) => exclude.clone(),
+            (None, None) => Vec::new(),
+        };
+        let filter = ImageFilter::new(include, exclude).with_suggestion(|| {
+            format!(
+                "Fix the patterns in {} and {}, e.g. \"*.png\" or \"thumbnails/**\"",
+                "include".bold().italic().blue(),
+                "exclude".bold().italic().blue(),
+            )
+        })?;
+
         let exec = match (&self.exec, &default.exec) {
             (Some(exec), _) | (None, Some(exec)) => Some(exec.to_path_buf()),
             (None, None) => None,
         };
 
         if let Some(exec_path) = &exec {
-            ensure!(
-                exec_path.exists(),
-                "Exec script {} must exist",
-                exec_path.to_string_lossy().italic().yellow()
-            );
-            ensure!(
-                exec_path.is_file(),
-                "Exec path {} must be a file",
-                exec_path.to_string_lossy().italic().yellow()
-            );
-            ensure!(
-                std::fs::metadata(exec_path)?.permissions().mode() & 0o111 != 0,
-                "Exec script {} must be executable",
-                exec_path.to_string_lossy().italic().yellow()
-            );
+            if !exec_path.exists() {
+                return Err(eyre!(
+                    "Exec script {} must exist",
+                    exec_path.to_string_lossy().italic().yellow()
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "Set attribute {} to the path of an existing script",
+                        "exec".bold().italic().blue(),
+                    )
+                });
+            }
+            if !exec_path.is_file() {
+                return Err(eyre!(
+                    "Exec path {} must be a file",
+                    exec_path.to_string_lossy().italic().yellow()
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "Set attribute {} to the path of a script, not a directory",
+                        "exec".bold().italic().blue(),
+                    )
+                });
+            }
+            if std::fs::metadata(exec_path)?.permissions().mode() & 0o111 == 0 {
+                return Err(eyre!(
+                    "Exec script {} must be executable",
+                    exec_path.to_string_lossy().italic().yellow()
+                ))
+                .with_suggestion(|| format!("Run chmod +x {}", exec_path.to_string_lossy()));
+            }
         }
 
         Ok(WallpaperInfo {
@@ -274,13 +388,73 @@ impl SerializedWallpaperInfo {
             transition,
             offset,
             recursive,
+            filter,
             exec,
         })
     }
 }
 
+/// Settings of the IPC socket, in the `[ipc]` section
+#[derive(Default, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IpcConfig {
+    /// Users allowed to connect to the socket, besides the one running wpaperd
+    #[serde(default, rename = "allowed-users")]
+    pub allowed_users: Vec<u32>,
+    /// Groups whose members are allowed to connect to the socket
+    #[serde(default, rename = "allowed-groups")]
+    pub allowed_groups: Vec<u32>,
+    /// Users having one of `allowed_groups` as supplementary group, resolved when the
+    /// configuration is loaded so that no lookup happens when a client connects
+    #[serde(skip)]
+    group_members: Vec<u32>,
+}
+
+impl IpcConfig {
+    /// Return true if users other than the one running wpaperd can connect
+    pub fn allows_others(&self) -> bool {
+        !self.allowed_users.is_empty() || !self.allowed_groups.is_empty()
+    }
+
+    /// Return true if the user `uid`, whose primary group is `gid`, can connect
+    pub fn allows(&self, uid: u32, gid: u32) -> bool {
+        self.allowed_users.contains(&uid)
+            || self.allowed_groups.contains(&gid)
+            || self.group_members.contains(&uid)
+    }
+
+    /// Look for the members of the allowed groups in the user database
+    fn resolve_group_members(&mut self) {
+        self.group_members = self
+            .allowed_groups
+            .iter()
+            .filter_map(|gid| match Group::from_gid(Gid::from_raw(*gid)) {
+                Ok(group) => group,
+                Err(err) => {
+                    warn!("Failed to look for group {gid}: {err}");
+                    None
+                }
+            })
+            .flat_map(|group| group.mem)
+            .filter_map(|name| match User::from_name(&name) {
+                Ok(user) => user.map(|user| user.uid.as_raw()),
+                Err(err) => {
+                    warn!("Failed to look for user {name}: {err}");
+                    None
+                }
+            })
+            .collect();
+    }
+}
+
 #[derive(Deserialize, Default)]
 pub struct Config {
+    /// Other configuration files to read, relative to this one; their sections replace the
+    /// ones of this file
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    pub ipc: IpcConfig,
     #[serde(flatten)]
     data: HashMap<String, SerializedWallpaperInfo>,
     #[serde(skip)]
@@ -291,12 +465,110 @@ pub struct Config {
     pub path: PathBuf,
     #[serde(skip)]
     pub reloaded: Option<Arc<AtomicBool>>,
+    /// Directory of the drop-in files, only set for the default configuration file
+    #[serde(skip)]
+    pub conf_d: Option<PathBuf>,
+    /// Files the configuration has been read from, in order
+    #[serde(skip)]
+    files: Vec<PathBuf>,
+    /// File each section has been read from
+    #[serde(skip)]
+    sources: HashMap<String, PathBuf>,
+    /// The `re:` and `match` sections, in the order they are tried
+    #[serde(skip)]
+    matchers: Vec<OutputMatcher>,
+    /// The sections validated by [`Config::load`], used to cache their paths
+    #[serde(skip)]
+    infos: HashMap<String, WallpaperInfo>,
+}
+
+/// A section matching the displays by their attributes instead of its name
+struct OutputMatcher {
+    section: String,
+    pattern: OutputPattern,
+    priority: i32,
+}
+
+enum OutputPattern {
+    /// Regular expression of a `re:` section, matching the description
+    Regex(Regex),
+    Attributes(OutputMatch),
+}
+
+impl OutputPattern {
+    fn matches(&self, display: &DisplayInfo) -> bool {
+        match self {
+            OutputPattern::Regex(regex) => regex.is_match(&display.description),
+            OutputPattern::Attributes(output_match) => output_match.matches(display),
+        }
+    }
+}
+
+/// Problems found while loading a configuration file that did not prevent it from being used
+#[derive(Default, Debug)]
+pub struct ConfigDiagnostics {
+    /// Sections that failed the validation and have been ignored
+    pub errors: Vec<(String, Report)>,
+    pub warnings: Vec<(String, Report)>,
+}
+
+impl ConfigDiagnostics {
+    pub fn log(&self) {
+        for (_, err) in self.errors.iter().chain(&self.warnings) {
+            // We do not want to exit when error occurs, print it and go forward
+            warn!("{err:?}");
+        }
+    }
 }
 
 impl Config {
-    pub fn new_from_path(path: &Path) -> Result<Self> {
+    pub fn new_from_path(path: &Path, conf_d: Option<&Path>) -> Result<Self> {
+        let (config, diagnostics) = Self::load(path, conf_d)?;
+        diagnostics.log();
+        Ok(config)
+    }
+
+    /// Read and validate the configuration at `path`, followed by the drop-in files in
+    /// `conf_d`. The sections that are not valid are dropped and reported in the diagnostics,
+    /// an error is only returned when the file itself cannot be read.
+    pub fn load(path: &Path, conf_d: Option<&Path>) -> Result<(Self, ConfigDiagnostics)> {
         ensure!(path.exists(), "File {path:?} does not exist");
-        let mut config: Self = toml::from_str(&fs::read_to_string(path)?)?;
+        let mut diagnostics = ConfigDiagnostics::default();
+        let mut config = Self::default();
+        let mut files = Vec::new();
+        config.merge_file(path, &mut files)?;
+        // The drop-in files are read last, in lexical order
+        if let Some(conf_d) = conf_d.filter(|conf_d| conf_d.is_dir()) {
+            let mut drop_ins: Vec<PathBuf> = fs::read_dir(conf_d)
+                .wrap_err_with(|| format!("Failed to read directory {conf_d:?}"))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "toml"))
+                .collect();
+            drop_ins.sort();
+            for drop_in in drop_ins {
+                config.merge_file(&drop_in, &mut files)?;
+            }
+        }
+        config.files = files;
+        config.ipc.resolve_group_members();
+        config.conf_d = conf_d.map(Path::to_path_buf);
+        config
+            .data
+            .retain(|name, info| match info.expand_variables() {
+                Ok(()) => true,
+                Err(err) => {
+                    diagnostics.errors.push((
+                        name.clone(),
+                        err.wrap_err(format!(
+                            "Failed to expand the paths of section {} in {:?}",
+                            name.bold().magenta(),
+                            config.sources[name]
+                        )),
+                    ));
+                    false
+                }
+            });
         config
             .data
             .get("default")
@@ -307,27 +579,109 @@ impl Config {
             .get("any")
             .unwrap_or(&SerializedWallpaperInfo::default())
             .clone_into(&mut config.any);
+        let mut matchers = Vec::new();
+        let mut infos = HashMap::new();
         config.data.retain(|name, info| {
+            let regex = match name.strip_prefix("re:").map(Regex::new) {
+                Some(Ok(regex)) => Some(regex),
+                Some(Err(err)) => {
+                    diagnostics.errors.push((
+                        name.clone(),
+                        Report::new(err)
+                            .wrap_err(format!(
+                                "Invalid regular expression in section {} of {:?}",
+                                name.bold().magenta(),
+                                config.sources[name]
+                            ))
+                            .with_suggestion(|| {
+                                "Fix the regular expression following the re: prefix".to_string()
+                            }),
+                    ));
+                    return false;
+                }
+                None => None,
+            };
+            let pattern = match (regex, &info.output_match) {
+                (Some(_), Some(_)) => {
+                    diagnostics.errors.push((
+                        name.clone(),
+                        eyre!(
+                            "Section {} of {:?} cannot have both the re: prefix and a {} table",
+                            name.bold().magenta(),
+                            config.sources[name],
+                            "match".bold().italic().blue(),
+                        )
+                        .suggestion("Remove either the re: prefix or the match table"),
+                    ));
+                    return false;
+                }
+                (_, Some(output_match)) if output_match.is_empty() => {
+                    diagnostics.errors.push((
+                        name.clone(),
+                        eyre!(
+                            "The {} table of section {} in {:?} is empty",
+                            "match".bold().italic().blue(),
+                            name.bold().magenta(),
+                            config.sources[name],
+                        )
+                        .suggestion("Set at least one of make, model and serial"),
+                    ));
+                    return false;
+                }
+                (Some(regex), None) => Some(OutputPattern::Regex(regex)),
+                (None, Some(output_match)) => {
+                    Some(OutputPattern::Attributes(output_match.clone()))
+                }
+                (None, None) => None,
+            };
+            if pattern.is_none() && info.priority.is_some() {
+                diagnostics.warnings.push((
+                    name.clone(),
+                    eyre!(
+                        "Attribute {} is ignored in section {}, it is only used by re: and match sections",
+                        "priority".bold().italic().blue(),
+                        name.bold().magenta()
+                    ),
+                ));
+            }
+
             // The default configuration does not follow these rules
             // We still need the default configuration here because the path needs to be cached
-            if info == &config.default {
-                true
-            } else {
-                match info.apply_and_validate(&config.default).wrap_err_with(|| {
-                    format!(
-                        "Failed to validate configuration for display {}",
-                        name.bold().magenta()
-                    )
-                }) {
-                    Ok(_) => true,
-                    Err(err) => {
-                        // We do not want to exit when error occurs, print it and go forward
-                        warn!("{err:?}");
-                        false
-                    }
+            let valid = match info.apply_and_validate(&config.default).wrap_err_with(|| {
+                format!(
+                    "Failed to validate configuration for display {} in {:?}",
+                    name.bold().magenta(),
+                    config.sources[name]
+                )
+            }) {
+                Ok(validated) => {
+                    infos.insert(name.clone(), validated);
+                    true
                 }
+                Err(_) if info == &config.default => true,
+                Err(err) => {
+                    diagnostics.errors.push((name.clone(), err));
+                    false
+                }
+            };
+            if let (true, Some(pattern)) = (valid, pattern) {
+                matchers.push(OutputMatcher {
+                    section: name.clone(),
+                    pattern,
+                    priority: info.priority.unwrap_or_default(),
+                });
             }
+            valid
+        });
+        // The highest priority wins, then the sections are tried in alphabetical order so that
+        // the same section is always used
+        matchers.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.section.cmp(&b.section))
         });
+        config.matchers = matchers;
+        config.infos = infos;
 
         let groups = config
             .data
@@ -364,15 +718,18 @@ impl Config {
                         || x.1.sorting != y.1.sorting
                         || x.1.path == y.1.path)
                     {
-                        warn!(
-                            "Displays {} and {} are assigned to group {} but have different paths",
-                            x.0,
-                            y.0,
-                            match x.1.sorting.unwrap() {
-                                Sorting::GroupedRandom { group } => group,
-                                _ => unreachable!(),
-                            }
-                        );
+                        diagnostics.warnings.push((
+                            x.0.clone(),
+                            eyre!(
+                                "Displays {} and {} are assigned to group {} but have different paths",
+                                x.0,
+                                y.0,
+                                match x.1.sorting.unwrap() {
+                                    Sorting::GroupedRandom { group } => group,
+                                    _ => unreachable!(),
+                                }
+                            ),
+                        ));
                         errored_list.push(j);
                     }
                 }
@@ -380,66 +737,169 @@ impl Config {
         }
 
         config.path = path.to_path_buf();
-        Ok(config)
+        Ok((config, diagnostics))
     }
 
-    pub fn get_info_for_output(&self, name: &str, description: &str) -> Result<WallpaperInfo> {
-        use regex::Regex;
+    /// Read the configuration file at `path` and then the files it includes, so that their
+    /// sections replace the ones read before. `files` lists the files read so far, a file is
+    /// never read twice.
+    fn merge_file(&mut self, path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let path =
+            fs::canonicalize(path).wrap_err_with(|| format!("File {path:?} does not exist"))?;
+        if files.contains(&path) {
+            return Ok(());
+        }
+        files.push(path.clone());
+
+        let file: Self = toml::from_str(
+            &fs::read_to_string(&path).wrap_err_with(|| format!("Failed to read {path:?}"))?,
+        )
+        .wrap_err_with(|| format!("Failed to parse the configuration in {path:?}"))?;
+        if file.ipc != IpcConfig::default() {
+            self.ipc = file.ipc;
+        }
+        for (name, info) in file.data {
+            self.sources.insert(name.clone(), path.clone());
+            self.data.insert(name, info);
+        }
+
+        // Relative paths start from the directory of the file including them
+        let dir = path.parent().unwrap_or(Path::new("/"));
+        for include in file.include {
+            let include = expand_variables(&expand_tilde(&include))
+                .wrap_err_with(|| format!("Failed to expand an include of {path:?}"))?;
+            self.merge_file(&dir.join(include), files)?;
+        }
+        Ok(())
+    }
+
+    /// Paths to watch for changes: the files the configuration has been read from and the
+    /// conf.d directory, where new files can be added
+    pub fn watched_paths(&self) -> Vec<PathBuf> {
+        let mut paths = if self.files.is_empty() {
+            // The configuration could not be read, wait for the file to be fixed
+            vec![self.path.clone()]
+        } else {
+            self.files.clone()
+        };
+        if let Some(conf_d) = self.conf_d.as_ref().filter(|conf_d| conf_d.is_dir()) {
+            paths.push(conf_d.clone());
+        }
+        paths
+    }
+
+    /// Checks that are too slow to be run on every reload, used by `wpaperd --check-config`:
+    /// every directory set as `path` must contain at least one image. The default section is
+    /// validated here as well, as [`Config::load`] skips it.
+    pub fn check_paths(&self, diagnostics: &mut ConfigDiagnostics) {
+        for (name, info) in &self.data {
+            if info == &self.default && info.path.is_none() {
+                continue;
+            }
+            let info = match info.apply_and_validate(&self.default) {
+                Ok(info) => info,
+                Err(err) if info == &self.default => {
+                    diagnostics.errors.push((
+                        name.clone(),
+                        err.wrap_err(format!(
+                            "Failed to validate configuration for section {}",
+                            name.bold().magenta()
+                        )),
+                    ));
+                    continue;
+                }
+                // Already reported by Config::load
+                Err(_) => continue,
+            };
+            for path in info.path.paths() {
+                if path.is_dir()
+                    && list_images(path, info.recursive.unwrap_or_default(), &info.filter)
+                        .is_empty()
+                {
+                    diagnostics.errors.push((
+                        name.clone(),
+                        eyre!(
+                            "Directory {} set for display {} does not contain any image",
+                            path.to_string_lossy().italic().yellow(),
+                            name.bold().magenta()
+                        )
+                        .with_suggestion(|| {
+                            format!(
+                                "Add some images to the directory or set {} to true if they \
+                                are in its subdirectories",
+                                "recursive".bold().italic().blue()
+                            )
+                        }),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Name of the section used for a display: the first `re:` or `match` section matching it,
+    /// the section named after its description or its name, and `any` otherwise
+    pub fn matching_section<'a>(&'a self, display: &'a DisplayInfo) -> &'a str {
         // Actually, wayland may report an output description in different
         // formats as the compositors wants to. For example, niri reports
         // description in format `<vendor name> - <monitor name> - <port name>`
         // so, we can not handle here the all formats at once. But users
         // can use a regex to match theirs' compositors outputs.
-        let mut matched = None;
-        for (k, v) in self.data.iter() {
-            if !k.starts_with("re:") {
-                continue;
-            }
-            // TODO(Shvedov): Better to compile re withing creation of
-            // WallpaperInfo, but adding field of type `Option<Regex>` breaks
-            // PatialEq macro.
-            let re = Regex::new(&k[3..]).unwrap();
-            if re.is_match(description) {
-                matched = Some(v);
-                break;
-            };
+        if let Some(matcher) = self
+            .matchers
+            .iter()
+            .find(|matcher| matcher.pattern.matches(display))
+        {
+            return &matcher.section;
         }
 
-        if let Some(info) = matched {
-            info.apply_and_validate(&self.default)
+        let description = clean_monitor_description(&display.description);
+        if self.data.contains_key(description) {
+            description
+        } else if self.data.contains_key(&display.name) {
+            &display.name
         } else {
-            self.data
-                .get(clean_monitor_description(description))
-                .or_else(|| self.data.get(name))
-                .unwrap_or(&self.any)
-                .apply_and_validate(&self.default)
+            "any"
         }
     }
 
+    pub fn get_info_for_output(&self, display: &DisplayInfo) -> Result<WallpaperInfo> {
+        self.data
+            .get(self.matching_section(display))
+            .unwrap_or(&self.any)
+            .apply_and_validate(&self.default)
+    }
+
     pub fn listen_to_changes(&self, hotwatch: &mut Hotwatch, ping: Ping) -> Result<()> {
-        let reloaded = self.reloaded.as_ref().unwrap().clone();
-        hotwatch
-            .watch(&self.path, move |event: Event| {
-                if let hotwatch::EventKind::Modify(_) = event.kind {
-                    reloaded.store(true, Ordering::Relaxed);
-                    ping.ping();
-                }
-            })
-            .wrap_err_with(|| format!("Failed to watch file changes for {:?}", &self.path))?;
+        for path in self.watched_paths() {
+            let reloaded = self.reloaded.as_ref().unwrap().clone();
+            let ping = ping.clone();
+            hotwatch
+                .watch(&path, move |event: Event| {
+                    // Files can be added to or removed from conf.d
+                    if let hotwatch::EventKind::Modify(_)
+                    | hotwatch::EventKind::Create(_)
+                    | hotwatch::EventKind::Remove(_) = event.kind
+                    {
+                        reloaded.store(true, Ordering::Relaxed);
+                        ping.ping();
+                    }
+                })
+                .wrap_err_with(|| format!("Failed to watch file changes for {path:?}"))?;
+        }
         Ok(())
     }
 
-    pub fn paths(&self) -> Vec<(PathBuf, Recursive)> {
+    /// Paths to cache, with the settings used to list their images
+    pub fn paths(&self) -> Vec<(PathBuf, Recursive, ImageFilter)> {
         let mut paths: Vec<_> = self
-            .data
+            .infos
             .values()
-            .filter_map(|info| {
-                info.path.as_ref().map(|p| {
-                    (
-                        p.to_path_buf(),
-                        info.recursive.map(Recursive::from).unwrap_or_default(),
-                    )
-                })
+            .flat_map(|info| {
+                let recursive = info.recursive.unwrap_or_default();
+                info.path
+                    .paths()
+                    .map(|path| (path.to_path_buf(), recursive, info.filter.clone()))
+                    .collect::<Vec<_>>()
             })
             .collect();
         paths.sort_unstable();
@@ -450,12 +910,13 @@ impl Config {
     /// Return true if the struct changed
     pub fn update(&mut self) -> bool {
         // When the config file has been written into
-        let new_config = Config::new_from_path(&self.path).wrap_err_with(|| {
-            format!(
-                "Failed to read the new configuration from {}",
-                self.path.to_string_lossy()
-            )
-        });
+        let new_config =
+            Config::new_from_path(&self.path, self.conf_d.as_deref()).wrap_err_with(|| {
+                format!(
+                    "Failed to read the new configuration from {}",
+                    self.path.to_string_lossy()
+                )
+            });
         match new_config {
             Ok(new_config) if new_config != *self => {
                 let reloaded = self.reloaded.as_ref().unwrap().clone();
@@ -477,7 +938,7 @@ impl Config {
 
 impl PartialEq for Config {
     fn eq(&self, other: &Self) -> bool {
-        self.data == other.data
+        self.data == other.data && self.ipc == other.ipc && self.files == other.files
     }
 }
 
@@ -486,12 +947,126 @@ where
     D: serde::Deserializer<'de>,
 {
     let path = String::deserialize(deserializer)?;
-    let path = Path::new(&path);
+    Ok(Some(expand_tilde(Path::new(&path))))
+}
 
-    Ok(Some(
-        path.strip_prefix("~")
-            .map_or(path.to_path_buf(), |p| home_dir().unwrap().join(p)),
-    ))
+/// `path` can be a single path or a list of paths, each one optionally with a weight
+fn path_deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<PathSource>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "a path or a list of paths")]
+    enum SerializedPath {
+        Single(PathBuf),
+        Multiple(Vec<SerializedSource>),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(
+        untagged,
+        expecting = "a path or a table with the keys path and weight"
+    )]
+    enum SerializedSource {
+        Path(PathBuf),
+        Weighted(WeightedSource),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct WeightedSource {
+        path: PathBuf,
+        weight: Option<u32>,
+    }
+
+    let source = |path: PathBuf, weight| PathSource {
+        path: expand_tilde(&path),
+        weight,
+    };
+    Ok(Some(match SerializedPath::deserialize(deserializer)? {
+        SerializedPath::Single(path) => vec![source(path, 1)],
+        SerializedPath::Multiple(sources) => sources
+            .into_iter()
+            .map(|serialized| match serialized {
+                SerializedSource::Path(path) => source(path, 1),
+                SerializedSource::Weighted(WeightedSource { path, weight }) => {
+                    source(path, weight.unwrap_or(1))
+                }
+            })
+            .collect(),
+    }))
+}
+
+fn expand_tilde(path: &Path) -> PathBuf {
+    path.strip_prefix("~")
+        .map_or(path.to_path_buf(), |p| home_dir().unwrap().join(p))
+}
+
+/// Expand the `$VAR` and `${VAR}` in a path; the XDG user directories, like
+/// `$XDG_PICTURES_DIR`, are read from `user-dirs.dirs` when they are not in the environment
+fn expand_variables(path: &Path) -> Result<PathBuf> {
+    let Some(mut rest) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+    let mut expanded = String::new();
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| eyre!("Missing closing brace after ${{ in {path:?}"))?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            // A $ that isn't followed by a variable name is kept as it is
+            if end == 0 {
+                expanded.push('$');
+                continue;
+            }
+            (&rest[..end], &rest[end..])
+        };
+        let value = lookup_variable(name).ok_or_else(|| {
+            eyre!(
+                "Variable {} used in {path:?} is not defined",
+                format!("${name}").bold().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Set {name} in the environment wpaperd is started from, or write the path without it"
+                )
+            })
+        })?;
+        expanded.push_str(&value);
+        rest = after;
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+fn lookup_variable(name: &str) -> Option<String> {
+    if let Ok(value) = std::env::var(name) {
+        return Some(value);
+    }
+    let dir = match name {
+        "HOME" => home_dir(),
+        "XDG_CONFIG_HOME" => dirs::config_dir(),
+        "XDG_CACHE_HOME" => dirs::cache_dir(),
+        "XDG_DATA_HOME" => dirs::data_dir(),
+        "XDG_STATE_HOME" => dirs::state_dir(),
+        "XDG_DESKTOP_DIR" => dirs::desktop_dir(),
+        "XDG_DOCUMENTS_DIR" => dirs::document_dir(),
+        "XDG_DOWNLOAD_DIR" => dirs::download_dir(),
+        "XDG_MUSIC_DIR" => dirs::audio_dir(),
+        "XDG_PICTURES_DIR" => dirs::picture_dir(),
+        "XDG_PUBLICSHARE_DIR" => dirs::public_dir(),
+        "XDG_TEMPLATES_DIR" => dirs::template_dir(),
+        "XDG_VIDEOS_DIR" => dirs::video_dir(),
+        _ => None,
+    };
+    dir.map(|dir| dir.to_string_lossy().into_owned())
 }
 
 /// Clean a monitor description so that the value reported by Wayland matches the one reported by
@@ -511,7 +1086,283 @@ fn clean_monitor_description(desc: &str) -> &str {
 
 #[cfg(test)]
 mod test {
+    use std::ops::Deref;
+
     use super::*;
+    use crate::display_info::test::display;
+
+    /// Temporary directory of a test, removed when the test ends even if it fails
+    pub struct TestDir(PathBuf);
+
+    impl TestDir {
+        pub fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("wpaperd-test-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Write `content` in the config.toml of the directory and load it
+        pub fn load_config(&self, content: &str) -> (Config, ConfigDiagnostics) {
+            let config_file = self.join("config.toml");
+            fs::write(&config_file, content).unwrap();
+            Config::load(&config_file, None).unwrap()
+        }
+    }
+
+    impl Deref for TestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_ipc_section() {
+        let config: Config = toml::from_str(
+            r#"
+[ipc]
+allowed-users = [1001]
+allowed-groups = [100]
+
+[default]
+path = "/usr/share/wallpapers"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.ipc.allowed_users, [1001]);
+        assert!(config.ipc.allows(1001, 1001));
+        assert!(config.ipc.allows(1002, 100));
+        assert!(!config.ipc.allows(1003, 1003));
+        // Members of the allowed groups through their supplementary groups
+        let mut ipc = config.ipc.clone();
+        ipc.group_members = vec![1003];
+        assert!(ipc.allows(1003, 1003));
+        // The ipc section is not a display
+        assert!(!config.data.contains_key("ipc"));
+        assert!(config.data.contains_key("default"));
+    }
+
+    #[test]
+    fn test_invalid_regex_section() {
+        let dir = TestDir::new("invalid-regex");
+        let (config, diagnostics) = dir.load_config(&format!(
+            "[default]\npath = {:?}\n\n[\"re:DP-(\"]\n\n[\"re:^DP-\\\\d+$\"]\n",
+            &*dir
+        ));
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert_eq!(diagnostics.errors[0].0, "re:DP-(");
+        assert!(!config.data.contains_key("re:DP-("));
+        assert!(config.data.contains_key("re:^DP-\\d+$"));
+    }
+
+    #[test]
+    fn test_regex_priority() {
+        let dir = TestDir::new("regex-priority");
+        let (config, diagnostics) = dir.load_config(&format!(
+            "[default]\npath = {:?}\n\n[\"re:LG\"]\n\n[\"re:^LG\"]\n\n\
+            [\"re:Dell\"]\npriority = -1\n\n[\"re:Dell U\"]\n\n[DP-1]\npriority = 1\n",
+            &*dir
+        ));
+        assert!(diagnostics.errors.is_empty());
+        // priority is ignored outside of the re: sections
+        assert_eq!(diagnostics.warnings.len(), 1);
+        assert_eq!(diagnostics.warnings[0].0, "DP-1");
+        let section = |name, description| {
+            config
+                .matching_section(&display(name, description, "", ""))
+                .to_string()
+        };
+        // Same priority, the first in alphabetical order wins
+        assert_eq!(section("DP-1", "LG Ultra"), "re:LG");
+        assert_eq!(section("DP-1", "Dell U2720"), "re:Dell U");
+        assert_eq!(section("DP-1", "Samsung"), "DP-1");
+        assert_eq!(section("DP-2", "Samsung"), "any");
+    }
+
+    #[test]
+    fn test_match_section() {
+        let dir = TestDir::new("match-section");
+        let (config, diagnostics) = dir.load_config(&format!(
+            "[default]\npath = {:?}\n\n\
+            [dock]\nmatch = {{ make = \"Dell Inc.\", model = \"DELL U2720Q\" }}\n\n\
+            [office]\nmatch = {{ serial = \"8RMJ123\" }}\npriority = 1\n\n\
+            [empty]\nmatch = {{}}\n",
+            &*dir
+        ));
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert_eq!(diagnostics.errors[0].0, "empty");
+        let description = "Dell Inc. DELL U2720Q 8RMJ123 (DP-3)";
+        let office = display("DP-3", description, "Dell Inc.", "DELL U2720Q");
+        assert_eq!(office.serial(), "8RMJ123");
+        assert_eq!(config.matching_section(&office), "office");
+        let description = "Dell Inc. DELL U2720Q 5XCV987 (DP-7)";
+        let other = display("DP-7", description, "Dell Inc.", "DELL U2720Q");
+        assert_eq!(config.matching_section(&other), "dock");
+        let laptop = display("eDP-1", "BOE 0x095F (eDP-1)", "BOE", "0x095F");
+        assert_eq!(config.matching_section(&laptop), "any");
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        std::env::set_var("WPAPERD_TEST_ROOT", "/srv/walls");
+        assert_eq!(
+            expand_variables(Path::new("$WPAPERD_TEST_ROOT/nature")).unwrap(),
+            Path::new("/srv/walls/nature")
+        );
+        assert_eq!(
+            expand_variables(Path::new("${WPAPERD_TEST_ROOT}_4k/$")).unwrap(),
+            Path::new("/srv/walls_4k/$")
+        );
+        assert_eq!(
+            expand_variables(Path::new("/price$.jpg")).unwrap(),
+            Path::new("/price$.jpg")
+        );
+        assert!(expand_variables(Path::new("$WPAPERD_TEST_UNDEFINED/walls")).is_err());
+        assert!(expand_variables(Path::new("${WPAPERD_TEST_ROOT/walls")).is_err());
+    }
+
+    #[test]
+    fn test_multiple_paths() {
+        let dir = TestDir::new("multiple-paths");
+        fs::create_dir_all(dir.join("nature")).unwrap();
+        fs::write(dir.join("fav.png"), "").unwrap();
+        let (config, diagnostics) = dir.load_config(&format!(
+            "[DP-1]\npath = [{:?}, {{ path = {:?}, weight = 3 }}]\nduration = \"10m\"\n\n\
+            [DP-2]\npath = []\n\n[DP-3]\npath = [{{ path = {:?}, weight = 0 }}]\n",
+            dir.join("nature"),
+            dir.join("fav.png"),
+            dir.join("fav.png"),
+        ));
+        let mut errors: Vec<_> = diagnostics.errors.iter().map(|(name, _)| name).collect();
+        errors.sort();
+        assert_eq!(errors, ["DP-2", "DP-3"]);
+        let info = config.data["DP-1"]
+            .apply_and_validate(&config.default)
+            .unwrap();
+        assert!(info.path.is_rotating());
+        assert_eq!(
+            info.path.sources(),
+            [
+                PathSource {
+                    path: dir.join("nature"),
+                    weight: 1
+                },
+                PathSource {
+                    path: dir.join("fav.png"),
+                    weight: 3
+                }
+            ]
+        );
+        assert_eq!(
+            config.paths(),
+            [
+                (dir.join("fav.png"), Recursive::On, ImageFilter::default()),
+                (dir.join("nature"), Recursive::On, ImageFilter::default())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_filter() {
+        let dir = TestDir::new("image-filter");
+        for subdir in ["thumbnails", "nature/nsfw", "space"] {
+            fs::create_dir_all(dir.join(subdir)).unwrap();
+        }
+        for file in [
+            "lake.png",
+            "lake.tmp.png",
+            "thumbnails/lake.png",
+            "nature/forest.jpg",
+            "nature/nsfw/beach.png",
+            "space/moon.png",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let (config, diagnostics) = dir.load_config(&format!(
+            "[default]\npath = {:?}\nexclude = [\"thumbnails\", \"*.tmp.png\", \"**/nsfw\"]\n\n\
+            [DP-1]\ninclude = [\"nature/**\", \"*.png\"]\nexclude = [\"space\"]\n\n\
+            [DP-2]\ninclude = [\"[a-\"]\n",
+            &*dir
+        ));
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert_eq!(diagnostics.errors[0].0, "DP-2");
+        let list = |section: &str| {
+            let info = config.data[section]
+                .apply_and_validate(&config.default)
+                .unwrap();
+            list_images(&dir, Recursive::On, &info.filter)
+        };
+        assert_eq!(
+            list("default"),
+            [
+                dir.join("lake.png"),
+                dir.join("nature/forest.jpg"),
+                dir.join("space/moon.png")
+            ]
+        );
+        // The section replaces the patterns of the default one
+        assert_eq!(
+            list("DP-1"),
+            [
+                dir.join("lake.png"),
+                dir.join("lake.tmp.png"),
+                dir.join("nature/forest.jpg"),
+                dir.join("nature/nsfw/beach.png"),
+                dir.join("thumbnails/lake.png"),
+            ]
+        );
+        // Each filter set is cached separately
+        assert_eq!(config.paths().len(), 2);
+    }
+
+    #[test]
+    fn test_include_and_conf_d() {
+        let dir = TestDir::new("include");
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::create_dir_all(dir.join("images")).unwrap();
+        let images = dir.join("images");
+        fs::write(dir.join("machine.toml"), "[DP-1]\nmode = \"stretch\"\n").unwrap();
+        fs::write(dir.join("conf.d/10-a.toml"), "[DP-2]\nmode = \"stretch\"\n").unwrap();
+        fs::write(dir.join("conf.d/20-b.toml"), "[DP-2]\nmode = \"tile\"\n").unwrap();
+        // Not a configuration file
+        fs::write(dir.join("conf.d/README"), "").unwrap();
+
+        // A configuration passed explicitly ignores the conf.d next to it
+        let (config, _) = dir.load_config(&format!(
+            "include = [\"machine.toml\"]\n\n[default]\npath = {images:?}\n\n\
+            [DP-1]\nmode = \"fit\"\n\n[DP-2]\nmode = \"fit\"\n"
+        ));
+        assert_eq!(config.data["DP-2"].mode, Some(BackgroundMode::Fit));
+        assert_eq!(config.files.len(), 2);
+        assert!(!config.watched_paths().contains(&dir.join("conf.d")));
+
+        let (config, diagnostics) =
+            Config::load(&dir.join("config.toml"), Some(&dir.join("conf.d"))).unwrap();
+        assert!(diagnostics.errors.is_empty());
+        assert_eq!(config.data["DP-1"].mode, Some(BackgroundMode::Stretch));
+        assert_eq!(config.data["DP-2"].mode, Some(BackgroundMode::Tile));
+        let root = fs::canonicalize(&*dir).unwrap();
+        assert_eq!(
+            config.files,
+            [
+                root.join("config.toml"),
+                root.join("machine.toml"),
+                root.join("conf.d/10-a.toml"),
+                root.join("conf.d/20-b.toml"),
+            ]
+        );
+        assert_eq!(config.sources["DP-2"], root.join("conf.d/20-b.toml"));
+        assert!(config.watched_paths().contains(&dir.join("conf.d")));
+    }
 
     #[test]
     fn test_clean_monitor_description() {
@@ -0,0 +1,726 @@
+//! D-Bus service of wpaperd, registered on the session bus as `org.wpaperd.Daemon`.
+//!
+//! The methods of `org.wpaperd.Daemon` mirror the [`IpcMessage`] operations; they are forwarded
+//! to the event loop through a calloop channel, like the requests coming from the IPC socket.
+//! Every display is exported as an `org.wpaperd.Display` object holding its state as properties,
+//! and the [`IpcEvent`] broadcasted to the subscribers are emitted as signals.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use humantime_serde::re::humantime;
+use log::warn;
+use nix::unistd::getuid;
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        channel::{self, Event},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+use wpaperd_ipc::{
+    DisplayDetails, GotoTarget, IpcError, IpcEvent, IpcMessage, IpcResponse, WallpaperOverrides,
+};
+use zbus::{
+    block_on,
+    blocking::{connection, Connection},
+    fdo, interface,
+    object_server::SignalEmitter,
+    zvariant::OwnedObjectPath,
+};
+
+use crate::ipc_server::handle_request;
+use crate::wpaperd::Wpaperd;
+
+const BUS_NAME: &str = "org.wpaperd.Daemon";
+const DAEMON_PATH: &str = "/org/wpaperd/Daemon";
+
+/// A method call waiting to be executed in the event loop
+struct Request {
+    message: IpcMessage,
+    reply: mpsc::Sender<Result<IpcResponse, IpcError>>,
+}
+
+/// Send the requests to the event loop and wait for their response
+#[derive(Clone)]
+struct Requests(Arc<Mutex<channel::Sender<Request>>>);
+
+impl Requests {
+    fn send(&self, message: IpcMessage) -> fdo::Result<IpcResponse> {
+        let (reply, response) = mpsc::channel();
+        self.0
+            .lock()
+            .expect("the lock not to be poisoned")
+            .send(Request { message, reply })
+            .map_err(|_| daemon_exiting())?;
+        response
+            .recv()
+            .map_err(|_| daemon_exiting())?
+            .map_err(|err| fdo::Error::Failed(err.to_string()))
+    }
+
+    fn send_ok(&self, message: IpcMessage) -> fdo::Result<()> {
+        self.send(message).map(|_| ())
+    }
+
+    /// Fail when the wallpaper could not be changed on any of the displays
+    fn send_to_displays(&self, message: IpcMessage) -> fdo::Result<()> {
+        match self.send(message)? {
+            IpcResponse::DisplayResults { results } => {
+                let errors: Vec<String> = results
+                    .into_iter()
+                    .filter_map(|result| {
+                        result
+                            .error
+                            .map(|error| format!("{}: {error}", result.display))
+                    })
+                    .collect();
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(fdo::Error::Failed(errors.join("; ")))
+                }
+            }
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn display(&self, name: &str) -> fdo::Result<DisplayDetails> {
+        match self.send(IpcMessage::ListDisplays {
+            monitors: vec![name.to_string()],
+        })? {
+            IpcResponse::Displays { mut displays } if !displays.is_empty() => {
+                Ok(displays.swap_remove(0))
+            }
+            _ => Err(unexpected_response()),
+        }
+    }
+}
+
+fn daemon_exiting() -> fdo::Error {
+    fdo::Error::Failed("wpaperd is exiting".to_string())
+}
+
+fn unexpected_response() -> fdo::Error {
+    fdo::Error::Failed("Unexpected response from wpaperd".to_string())
+}
+
+fn path_to_string(path: PathBuf) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// Object path of a display; the characters not allowed in a path are replaced with `_`
+fn display_path(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{DAEMON_PATH}/displays/{name}")
+}
+
+/// Parse the overrides, using the same keys and format as the configuration file
+fn parse_overrides(overrides: HashMap<String, String>) -> fdo::Result<WallpaperOverrides> {
+    let mut res = WallpaperOverrides::default();
+    for (key, value) in overrides {
+        match key.as_str() {
+            "duration" => {
+                res.duration = Some(humantime::parse_duration(&value).map_err(|err| {
+                    fdo::Error::InvalidArgs(format!("invalid duration {value:?}: {err}"))
+                })?)
+            }
+            "mode" => res.mode = Some(value),
+            "offset" => {
+                res.offset = Some(value.parse().map_err(|err| {
+                    fdo::Error::InvalidArgs(format!("invalid offset {value:?}: {err}"))
+                })?)
+            }
+            "transition" => res.transition = Some(value),
+            "sorting" => res.sorting = Some(value),
+            _ => return Err(fdo::Error::InvalidArgs(format!("unknown override {key:?}"))),
+        }
+    }
+    Ok(res)
+}
+
+struct Daemon {
+    requests: Requests,
+}
+
+#[interface(name = "org.wpaperd.Daemon")]
+impl Daemon {
+    fn next(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_to_displays(IpcMessage::NextWallpaper { monitors: displays })
+    }
+
+    fn previous(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_to_displays(IpcMessage::PreviousWallpaper { monitors: displays })
+    }
+
+    fn pause(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_ok(IpcMessage::PauseWallpaper { monitors: displays })
+    }
+
+    fn resume(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_ok(IpcMessage::ResumeWallpaper { monitors: displays })
+    }
+
+    fn toggle_pause(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_ok(IpcMessage::TogglePauseWallpaper { monitors: displays })
+    }
+
+    fn reload_wallpaper(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_to_displays(IpcMessage::ReloadWallpaper { monitors: displays })
+    }
+
+    fn current_wallpaper(&self, display: String) -> fdo::Result<String> {
+        match self
+            .requests
+            .send(IpcMessage::CurrentWallpaper { monitor: display })?
+        {
+            IpcResponse::CurrentWallpaper { path } => Ok(path_to_string(path)),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn all_wallpapers(&self) -> fdo::Result<HashMap<String, String>> {
+        match self.requests.send(IpcMessage::AllWallpapers)? {
+            IpcResponse::AllWallpapers { entries } => Ok(entries
+                .into_iter()
+                .map(|(display, path)| (display, path_to_string(path)))
+                .collect()),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn set_wallpaper(&self, displays: Vec<String>, path: String, sticky: bool) -> fdo::Result<()> {
+        self.requests.send_to_displays(IpcMessage::SetWallpaper {
+            monitors: displays,
+            path: PathBuf::from(path),
+            sticky,
+        })
+    }
+
+    fn unset_wallpaper(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_to_displays(IpcMessage::UnsetWallpaper { monitors: displays })
+    }
+
+    fn set_overrides(
+        &self,
+        displays: Vec<String>,
+        overrides: HashMap<String, String>,
+        until_reload: bool,
+    ) -> fdo::Result<()> {
+        self.requests.send_ok(IpcMessage::SetOverrides {
+            monitors: displays,
+            overrides: parse_overrides(overrides)?,
+            until_reload,
+        })
+    }
+
+    fn clear_overrides(&self, displays: Vec<String>) -> fdo::Result<()> {
+        self.requests
+            .send_ok(IpcMessage::ClearOverrides { monitors: displays })
+    }
+
+    fn list_images(&self, display: String) -> fdo::Result<Vec<String>> {
+        match self
+            .requests
+            .send(IpcMessage::ListImages { monitor: display })?
+        {
+            IpcResponse::Images { files, .. } => {
+                Ok(files.into_iter().map(path_to_string).collect())
+            }
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn history(&self, display: String) -> fdo::Result<(Vec<String>, u32)> {
+        match self
+            .requests
+            .send(IpcMessage::GetHistory { monitor: display })?
+        {
+            IpcResponse::History { entries, current } => Ok((
+                entries.into_iter().map(path_to_string).collect(),
+                current as u32,
+            )),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn jump_to_history(&self, display: String, index: u32) -> fdo::Result<()> {
+        self.requests.send_to_displays(IpcMessage::JumpToHistory {
+            monitor: display,
+            index: index as usize,
+        })
+    }
+
+    fn goto_index(&self, displays: Vec<String>, index: u32) -> fdo::Result<()> {
+        self.requests.send_to_displays(IpcMessage::GotoImage {
+            monitors: displays,
+            target: GotoTarget::Index(index as usize),
+        })
+    }
+
+    fn goto_name(&self, displays: Vec<String>, name: String) -> fdo::Result<()> {
+        self.requests.send_to_displays(IpcMessage::GotoImage {
+            monitors: displays,
+            target: GotoTarget::Name(name),
+        })
+    }
+
+    /// An empty path reloads the configuration file in use.
+    /// Returns the errors and the warnings found, as (section, message) pairs.
+    #[allow(clippy::type_complexity)]
+    fn reload_config(
+        &self,
+        path: String,
+    ) -> fdo::Result<(Vec<(String, String)>, Vec<(String, String)>)> {
+        let path = (!path.is_empty()).then(|| PathBuf::from(path));
+        match self.requests.send(IpcMessage::ReloadConfig { path })? {
+            IpcResponse::ConfigReloaded { errors, warnings } => {
+                let pairs = |diagnostics: Vec<wpaperd_ipc::ConfigDiagnostic>| {
+                    diagnostics
+                        .into_iter()
+                        .map(|diagnostic| (diagnostic.section, diagnostic.message))
+                        .collect()
+                };
+                Ok((pairs(errors), pairs(warnings)))
+            }
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    fn quit(&self) -> fdo::Result<()> {
+        self.requests.send_ok(IpcMessage::Shutdown)
+    }
+
+    #[zbus(property)]
+    fn version(&self) -> String {
+        env!("CARGO_PKG_VERSION").to_string()
+    }
+
+    /// Object paths of the `org.wpaperd.Display` objects
+    #[zbus(property)]
+    fn displays(&self) -> fdo::Result<Vec<OwnedObjectPath>> {
+        match self.requests.send(IpcMessage::AllWallpapers)? {
+            IpcResponse::AllWallpapers { entries } => entries
+                .into_iter()
+                .map(|(display, _)| {
+                    OwnedObjectPath::try_from(display_path(&display))
+                        .map_err(|err| fdo::Error::Failed(err.to_string()))
+                })
+                .collect(),
+            _ => Err(unexpected_response()),
+        }
+    }
+
+    #[zbus(signal)]
+    async fn wallpaper_changed(
+        emitter: &SignalEmitter<'_>,
+        display: &str,
+        path: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn paused(emitter: &SignalEmitter<'_>, display: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn resumed(emitter: &SignalEmitter<'_>, display: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn display_added(emitter: &SignalEmitter<'_>, display: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn display_removed(emitter: &SignalEmitter<'_>, display: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn config_reloaded(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn image_load_failed(
+        emitter: &SignalEmitter<'_>,
+        display: &str,
+        path: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// State of a single display, exported at `/org/wpaperd/Daemon/displays/<name>`
+struct DisplayObject {
+    name: String,
+    requests: Requests,
+}
+
+#[interface(name = "org.wpaperd.Display")]
+impl DisplayObject {
+    #[zbus(property)]
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[zbus(property)]
+    fn description(&self) -> fdo::Result<String> {
+        Ok(self.requests.display(&self.name)?.description)
+    }
+
+    #[zbus(property)]
+    fn width(&self) -> fdo::Result<i32> {
+        Ok(self.requests.display(&self.name)?.width)
+    }
+
+    #[zbus(property)]
+    fn height(&self) -> fdo::Result<i32> {
+        Ok(self.requests.display(&self.name)?.height)
+    }
+
+    #[zbus(property)]
+    fn scale(&self) -> fdo::Result<i32> {
+        Ok(self.requests.display(&self.name)?.scale)
+    }
+
+    /// Section of the configuration used for the display
+    #[zbus(property)]
+    fn section(&self) -> fdo::Result<String> {
+        Ok(self.requests.display(&self.name)?.section)
+    }
+
+    /// Images and directories configured for the display
+    #[zbus(property)]
+    fn paths(&self) -> fdo::Result<Vec<String>> {
+        Ok(self
+            .requests
+            .display(&self.name)?
+            .wallpaper
+            .path
+            .into_iter()
+            .map(|source| path_to_string(source.path))
+            .collect())
+    }
+
+    /// Image currently drawn
+    #[zbus(property)]
+    fn wallpaper(&self) -> fdo::Result<String> {
+        Ok(path_to_string(
+            self.requests.display(&self.name)?.current_image,
+        ))
+    }
+
+    #[zbus(property)]
+    fn paused(&self) -> fdo::Result<bool> {
+        Ok(self.requests.display(&self.name)?.paused)
+    }
+}
+
+fn export_display(conn: &Connection, name: &str, requests: &Requests) -> zbus::Result<()> {
+    conn.object_server().at(
+        display_path(name),
+        DisplayObject {
+            name: name.to_string(),
+            requests: requests.clone(),
+        },
+    )?;
+    Ok(())
+}
+
+/// Emit the signals matching an event and notify the changed properties
+fn emit_signals(conn: &Connection, requests: &Requests, event: IpcEvent) -> zbus::Result<()> {
+    let object_server = conn.object_server();
+    let daemon = object_server.interface::<_, Daemon>(DAEMON_PATH)?;
+    let emitter = daemon.signal_emitter();
+    match event {
+        IpcEvent::WallpaperChanged { monitor, path } => {
+            block_on(Daemon::wallpaper_changed(
+                emitter,
+                &monitor,
+                &path_to_string(path),
+            ))?;
+            let display = object_server.interface::<_, DisplayObject>(display_path(&monitor))?;
+            let iface = display.get();
+            block_on(iface.wallpaper_changed(display.signal_emitter()))
+        }
+        IpcEvent::Paused { monitor } => {
+            block_on(Daemon::paused(emitter, &monitor))?;
+            let display = object_server.interface::<_, DisplayObject>(display_path(&monitor))?;
+            let iface = display.get();
+            block_on(iface.paused_changed(display.signal_emitter()))
+        }
+        IpcEvent::Resumed { monitor } => {
+            block_on(Daemon::resumed(emitter, &monitor))?;
+            let display = object_server.interface::<_, DisplayObject>(display_path(&monitor))?;
+            let iface = display.get();
+            block_on(iface.paused_changed(display.signal_emitter()))
+        }
+        IpcEvent::DisplayAdded { monitor } => {
+            export_display(conn, &monitor, requests)?;
+            block_on(Daemon::display_added(emitter, &monitor))?;
+            block_on(daemon.get().displays_changed(emitter))
+        }
+        IpcEvent::DisplayRemoved { monitor } => {
+            object_server.remove::<DisplayObject, _>(display_path(&monitor))?;
+            block_on(Daemon::display_removed(emitter, &monitor))?;
+            block_on(daemon.get().displays_changed(emitter))
+        }
+        IpcEvent::ConfigReloaded => block_on(Daemon::config_reloaded(emitter)),
+        IpcEvent::ImageLoadFailed { monitor, path } => block_on(Daemon::image_load_failed(
+            emitter,
+            &monitor,
+            &path_to_string(path),
+        )),
+    }
+}
+
+/// Request the wpaperd name on the bus of `builder` and export the daemon object
+fn register(builder: connection::Builder<'_>, requests: &Requests) -> Result<Connection> {
+    builder
+        .name(BUS_NAME)
+        .wrap_err_with(|| format!("Failed to request the D-Bus name {BUS_NAME}"))?
+        .serve_at(
+            DAEMON_PATH,
+            Daemon {
+                requests: requests.clone(),
+            },
+        )
+        .wrap_err_with(|| format!("Failed to export the D-Bus object {DAEMON_PATH}"))?
+        .build()
+        .wrap_err("Failed to register wpaperd on the D-Bus session bus")
+}
+
+/// Register wpaperd on the session bus. The method calls are executed by the event loop, while
+/// the signals are emitted from a separate thread, so that a slow bus never stalls the drawing.
+pub fn start<'l>(
+    qh: QueueHandle<Wpaperd>,
+    ev_handle: &LoopHandle<'l, Wpaperd>,
+    wpaperd: &Wpaperd,
+) -> Result<()> {
+    let (sender, channel) = channel::channel::<Request>();
+    let requests = Requests(Arc::new(Mutex::new(sender)));
+
+    let conn = register(
+        connection::Builder::session().wrap_err("Failed to connect to the D-Bus session bus")?,
+        &requests,
+    )?;
+
+    let ev_handle_clone = ev_handle.clone();
+    ev_handle
+        .insert_source(channel, move |event, _, wpaperd| {
+            if let Event::Msg(request) = event {
+                // Only the user running the session bus can connect to it
+                let response = handle_request(
+                    request.message,
+                    getuid().as_raw(),
+                    &qh,
+                    &ev_handle_clone,
+                    wpaperd,
+                );
+                // The caller might have given up waiting, there is nobody to tell
+                let _ = request.reply.send(response);
+            }
+        })
+        .map_err(|err| eyre!("{err}"))
+        .wrap_err("Failed to insert the D-Bus requests into the event loop")?;
+
+    for surface in &wpaperd.surfaces {
+        export_display(&conn, surface.name(), &requests)
+            .wrap_err_with(|| format!("Failed to export display {} on D-Bus", surface.name()))?;
+    }
+
+    let (events, receiver) = mpsc::channel();
+    wpaperd
+        .subscribers
+        .borrow_mut()
+        .add_listener(Box::new(move |event: &IpcEvent| {
+            // The thread only stops when the process is exiting
+            let _ = events.send(event.clone());
+        }));
+    thread::Builder::new()
+        .name("wpaperd-dbus".to_string())
+        .spawn(move || {
+            for event in receiver {
+                if let Err(err) = emit_signals(&conn, &requests, event) {
+                    warn!("Failed to emit D-Bus signal: {err}");
+                }
+            }
+        })
+        .wrap_err("Failed to spawn the D-Bus thread")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+
+    use smithay_client_toolkit::reexports::calloop::EventLoop;
+    use wpaperd_ipc::{DisplayResult, WallpaperDetails};
+    use zbus::blocking::Proxy;
+
+    use super::*;
+
+    /// A private session bus, stopped when dropped
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("dbus-daemon to be installed");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn connect(&self) -> connection::Builder<'_> {
+            connection::Builder::address(self.address.as_str()).unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn display_details(name: &str) -> DisplayDetails {
+        DisplayDetails {
+            name: name.to_string(),
+            description: String::new(),
+            make: String::new(),
+            model: String::new(),
+            serial: String::new(),
+            width: 1920,
+            height: 1080,
+            scale: 1,
+            transform: "normal".to_string(),
+            section: "any".to_string(),
+            wallpaper: WallpaperDetails {
+                path: Vec::new(),
+                mode: "center".to_string(),
+                sorting: None,
+                duration: None,
+                transition: "fade".to_string(),
+                transition_time: 300,
+                group: None,
+                offset: None,
+                queue_size: 10,
+                recursive: true,
+                include: Vec::new(),
+                exclude: Vec::new(),
+                exec: None,
+            },
+            overrides: WallpaperOverrides::default(),
+            current_image: PathBuf::from("/walls/lake.png"),
+            paused: false,
+            has_context: true,
+        }
+    }
+
+    /// Answer the requests like the event loop of wpaperd would, with a single display DP-1
+    fn serve_requests(channel: channel::Channel<Request>) {
+        thread::spawn(move || {
+            let mut event_loop: EventLoop<()> = EventLoop::try_new().unwrap();
+            event_loop
+                .handle()
+                .insert_source(channel, |event, _, _| {
+                    if let Event::Msg(request) = event {
+                        let response = match request.message {
+                            IpcMessage::NextWallpaper { monitors } if monitors == ["DP-1"] => {
+                                Ok(IpcResponse::DisplayResults {
+                                    results: vec![DisplayResult {
+                                        display: "DP-1".to_string(),
+                                        error: None,
+                                    }],
+                                })
+                            }
+                            IpcMessage::ListDisplays { .. } => Ok(IpcResponse::Displays {
+                                displays: vec![display_details("DP-1")],
+                            }),
+                            _ => Err(IpcError::MonitorNotFound {
+                                monitor: "DP-2".to_string(),
+                            }),
+                        };
+                        let _ = request.reply.send(response);
+                    }
+                })
+                .unwrap();
+            event_loop.run(None, &mut (), |_| {}).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_session_bus() {
+        let bus = Bus::start();
+        let (sender, channel) = channel::channel::<Request>();
+        serve_requests(channel);
+        let requests = Requests(Arc::new(Mutex::new(sender)));
+        let conn = register(bus.connect(), &requests).unwrap();
+        export_display(&conn, "DP-1", &requests).unwrap();
+
+        let client = bus.connect().build().unwrap();
+        let daemon = Proxy::new(&client, BUS_NAME, DAEMON_PATH, "org.wpaperd.Daemon").unwrap();
+        daemon.call::<_, _, ()>("Next", &(vec!["DP-1"],)).unwrap();
+        assert!(daemon.call::<_, _, ()>("Next", &(vec!["DP-2"],)).is_err());
+
+        let display = Proxy::new(
+            &client,
+            BUS_NAME,
+            display_path("DP-1"),
+            "org.wpaperd.Display",
+        )
+        .unwrap();
+        let width: i32 = display.get_property("Width").unwrap();
+        assert_eq!(width, 1920);
+
+        // The signals of the daemon and the changes of the display properties are both emitted
+        let mut signals = daemon.receive_signal("WallpaperChanged").unwrap();
+        let mut changes = display.receive_property_changed::<String>("Wallpaper");
+        emit_signals(
+            &conn,
+            &requests,
+            IpcEvent::WallpaperChanged {
+                monitor: "DP-1".to_string(),
+                path: PathBuf::from("/walls/lake.png"),
+            },
+        )
+        .unwrap();
+        let signal = signals.next().unwrap();
+        let (name, path): (String, String) = signal.body().deserialize().unwrap();
+        assert_eq!(name, "DP-1");
+        assert_eq!(path, "/walls/lake.png");
+        assert_eq!(changes.next().unwrap().get().unwrap(), "/walls/lake.png");
+
+        let mut signals = daemon.receive_signal("Paused").unwrap();
+        emit_signals(
+            &conn,
+            &requests,
+            IpcEvent::Paused {
+                monitor: "DP-1".to_string(),
+            },
+        )
+        .unwrap();
+        let signal = signals.next().unwrap();
+        let name: String = signal.body().deserialize().unwrap();
+        assert_eq!(name, "DP-1");
+    }
+}
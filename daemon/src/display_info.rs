@@ -7,6 +7,8 @@ use smithay_client_toolkit::{
 pub struct DisplayInfo {
     pub name: String,
     pub description: String,
+    pub make: String,
+    pub model: String,
     pub width: i32,
     pub height: i32,
     pub scale: i32,
@@ -18,6 +20,8 @@ impl DisplayInfo {
         Self {
             name: info.name.unwrap_or_default(),
             description: info.description.unwrap_or_default(),
+            make: info.make,
+            model: info.model,
             width: 0,
             height: 0,
             scale: info.scale_factor,
@@ -97,7 +101,90 @@ impl DisplayInfo {
         }
     }
 
+    /// Name of the transform, as reported by the compositors
+    pub fn transform_name(&self) -> &'static str {
+        match self.transform {
+            Transform::Normal => "normal",
+            Transform::_90 => "90",
+            Transform::_180 => "180",
+            Transform::_270 => "270",
+            Transform::Flipped => "flipped",
+            Transform::Flipped90 => "flipped-90",
+            Transform::Flipped180 => "flipped-180",
+            Transform::Flipped270 => "flipped-270",
+            _ => "unknown",
+        }
+    }
+
+    /// Serial number of the display, empty if unknown
+    ///
+    /// wl_output doesn't report it, but wlroots based compositors and Hyprland add it to the
+    /// description after the make and the model, i.e. `Dell Inc. DELL U2720Q 8RMJ123 (DP-3)`
+    pub fn serial(&self) -> &str {
+        self.description
+            .strip_prefix(&self.make)
+            .and_then(|rest| rest.trim_start().strip_prefix(&self.model))
+            .map(|rest| rest.split_once(" (").map(|(s, _)| s).unwrap_or(rest).trim())
+            .unwrap_or_default()
+    }
+
     pub fn is_configured(&self) -> bool {
         self.width != 0 && self.height != 0
     }
 }
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    /// Display as reported by the compositor, used by the tests of the other modules as well
+    pub fn display(name: &str, description: &str, make: &str, model: &str) -> DisplayInfo {
+        DisplayInfo {
+            name: name.to_string(),
+            description: description.to_string(),
+            make: make.to_string(),
+            model: model.to_string(),
+            width: 0,
+            height: 0,
+            scale: 1,
+            transform: Transform::Normal,
+        }
+    }
+
+    #[test]
+    fn test_serial() {
+        // wlroots
+        let info = display(
+            "DP-3",
+            "Dell Inc. DELL U2720Q 8RMJ123 (DP-3)",
+            "Dell Inc.",
+            "DELL U2720Q",
+        );
+        assert_eq!(info.serial(), "8RMJ123");
+        let info = display(
+            "DP-3",
+            "Dell Inc. DELL U2720Q (DP-3)",
+            "Dell Inc.",
+            "DELL U2720Q",
+        );
+        assert_eq!(info.serial(), "");
+        // Hyprland
+        let info = display(
+            "DP-3",
+            "LG Electronics LG ULTRAGEAR 104NTMX1R123",
+            "LG Electronics",
+            "LG ULTRAGEAR",
+        );
+        assert_eq!(info.serial(), "104NTMX1R123");
+        // KDE
+        let info = display("DP-3", "Dell Inc. DELL U2720Q", "Dell Inc.", "DELL U2720Q");
+        assert_eq!(info.serial(), "");
+        let info = display(
+            "DP-3",
+            "Samsung Electric Company 27\"",
+            "Samsung Electric Company",
+            "C27F390",
+        );
+        assert_eq!(info.serial(), "");
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    cell::RefCell,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -12,68 +13,115 @@ use log::error;
 use smithay_client_toolkit::reexports::calloop::{self, ping::Ping, LoopHandle};
 use walkdir::WalkDir;
 
-use crate::{wallpaper_info::Recursive, wpaperd::Wpaperd};
+use crate::{
+    wallpaper_info::{ImageFilter, Recursive, WallpaperInfo, WallpaperPath},
+    wpaperd::Wpaperd,
+};
 
 #[derive(Debug)]
 struct Filelist {
     path: PathBuf,
     recursive: Recursive,
+    filter: ImageFilter,
     filelist: Arc<Vec<PathBuf>>,
+    /// Shared by all the filelists of the same directory, as it is watched only once
     outdated: Arc<AtomicBool>,
 }
 
 impl Filelist {
-    fn new(path: &Path, recursive: Recursive) -> Self {
+    fn new(
+        path: &Path,
+        recursive: Recursive,
+        filter: ImageFilter,
+        outdated: Arc<AtomicBool>,
+    ) -> Self {
         let mut res = Self {
             path: path.to_path_buf(),
             recursive,
+            filter,
             filelist: Arc::new(Vec::new()),
-            outdated: Arc::new(AtomicBool::new(true)),
+            outdated,
         };
         res.populate();
         res
     }
 
     fn populate(&mut self) {
-        self.filelist = Arc::new(
-            WalkDir::new(&self.path)
-                .max_depth(if self.recursive == Recursive::Off {
-                    1
-                } else {
-                    usize::MAX
-                })
-                .follow_links(true)
-                .sort_by_file_name()
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    if let Some(guess) = new_mime_guess::from_path(e.path()).first() {
-                        guess.type_() == "image"
-                    } else {
-                        false
-                    }
-                })
-                .map(|e| e.path().to_path_buf())
-                .collect(),
-        );
-        self.outdated.store(false, Ordering::Relaxed);
+        self.filelist = Arc::new(list_images(&self.path, self.recursive, &self.filter));
+    }
+
+    fn is_for(&self, path: &Path, recursive: Recursive, filter: &ImageFilter) -> bool {
+        self.path == path && self.recursive == recursive && &self.filter == filter
+    }
+}
+
+/// Return true if the MIME type of `path`, guessed from its extension, is an image
+pub fn is_image(path: &Path) -> bool {
+    if let Some(guess) = new_mime_guess::from_path(path).first() {
+        guess.type_() == "image"
+    } else {
+        false
+    }
+}
+
+/// Find the images in a directory that pass the filter, sorted by file name
+pub fn list_images(path: &Path, recursive: Recursive, filter: &ImageFilter) -> Vec<PathBuf> {
+    WalkDir::new(path)
+        .max_depth(if recursive == Recursive::Off {
+            1
+        } else {
+            usize::MAX
+        })
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        // Skip the excluded directories entirely, but never the root
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !e.file_type().is_dir()
+                || !filter.is_excluded(e.path().strip_prefix(path).unwrap_or(e.path()))
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| is_image(e.path()))
+        .filter(|e| filter.is_included(e.path().strip_prefix(path).unwrap_or(e.path())))
+        .map(|e| e.path().to_path_buf())
+        .collect()
+}
+
+/// Images of a display picking its wallpapers from multiple paths
+#[derive(Debug)]
+struct Union {
+    path: WallpaperPath,
+    recursive: Recursive,
+    filter: ImageFilter,
+    filelist: Arc<Vec<PathBuf>>,
+}
+
+impl Union {
+    fn is_for(&self, path: &WallpaperPath, recursive: Recursive, filter: &ImageFilter) -> bool {
+        &self.path == path && self.recursive == recursive && &self.filter == filter
     }
 }
 
 pub struct FilelistCache {
     cache: Vec<Filelist>,
+    /// Built on demand by `get`, dropped when one of their directories changes
+    unions: RefCell<Vec<Union>>,
 }
 
 impl FilelistCache {
     pub fn new(
-        paths: Vec<(PathBuf, Recursive)>,
+        paths: Vec<(PathBuf, Recursive, ImageFilter)>,
         hotwatch: &mut Hotwatch,
         event_loop_handle: LoopHandle<Wpaperd>,
     ) -> Result<(Ping, Self)> {
         let (ping, ping_source) =
             calloop::ping::make_ping().wrap_err("Failed to initialize a calloop::ping::Ping")?;
 
-        let mut filelist_cache = Self { cache: Vec::new() };
+        let mut filelist_cache = Self {
+            cache: Vec::new(),
+            unions: RefCell::new(Vec::new()),
+        };
         filelist_cache.update_paths(paths, hotwatch, ping.clone());
         event_loop_handle
             .insert_source(ping_source, move |_, _, wpaperd| {
@@ -85,85 +133,167 @@ impl FilelistCache {
         Ok((ping, filelist_cache))
     }
 
-    pub fn get(&self, path: &Path, recursive: Recursive) -> Arc<Vec<PathBuf>> {
-        debug_assert!(path.is_dir());
-        self.cache
+    /// Images that can be picked for `wallpaper_info`: the union of the images in its
+    /// directories and of its files, sorted as if they were in a single directory
+    pub fn get(&self, wallpaper_info: &WallpaperInfo) -> Arc<Vec<PathBuf>> {
+        let path = &wallpaper_info.path;
+        let recursive = wallpaper_info.recursive.unwrap_or_default();
+        let filter = &wallpaper_info.filter;
+        debug_assert!(path.is_rotating());
+        if let [source] = path.sources() {
+            return self.get_directory(&source.path, recursive, filter);
+        }
+        if let Some(union) = self
+            .unions
+            .borrow()
+            .iter()
+            .find(|union| union.is_for(path, recursive, filter))
+        {
+            return union.filelist.clone();
+        }
+        let mut files = Vec::new();
+        for path in path.paths() {
+            if path.is_dir() {
+                files.extend(self.get_directory(path, recursive, filter).iter().cloned());
+            } else if path.is_file() {
+                files.push(path.to_path_buf());
+            }
+        }
+        files.sort();
+        files.dedup();
+        let filelist = Arc::new(files);
+        self.unions.borrow_mut().push(Union {
+            path: path.clone(),
+            recursive,
+            filter: filter.clone(),
+            filelist: filelist.clone(),
+        });
+        filelist
+    }
+
+    fn get_directory(
+        &self,
+        path: &Path,
+        recursive: Recursive,
+        filter: &ImageFilter,
+    ) -> Arc<Vec<PathBuf>> {
+        match self
+            .cache
             .iter()
-            .find(|filelist| filelist.path == path && filelist.recursive == recursive)
-            .expect("Path passed to Filelist::get must have been cached")
-            .filelist
-            .clone()
+            .find(|filelist| filelist.is_for(path, recursive, filter))
+        {
+            Some(filelist) => filelist.filelist.clone(),
+            None => {
+                // The directory has been created or removed since the cache was last updated
+                error!("Directory {path:?} has not been cached, no image can be picked from it");
+                Arc::new(Vec::new())
+            }
+        }
     }
 
     /// paths must be sorted
     pub fn update_paths(
         &mut self,
-        paths: Vec<(PathBuf, Recursive)>,
+        paths: Vec<(PathBuf, Recursive, ImageFilter)>,
         hotwatch: &mut Hotwatch,
         event_loop_ping: Ping,
     ) {
+        let mut removed = Vec::new();
         self.cache.retain(|filelist| {
             let path_exists = filelist.path.exists();
             if paths
                 .iter()
-                .any(|(path, recursive)| &filelist.path == path && filelist.recursive == *recursive)
+                .any(|(path, recursive, filter)| filelist.is_for(path, *recursive, filter))
                 && path_exists
             {
                 true
             } else {
-                // Stop watching paths that have been removed
-                // Check that it exists before
-                if path_exists {
-                    if let Err(err) = hotwatch.unwatch(&filelist.path).wrap_err_with(|| {
-                        format!("Failed to unwatch changes for file {:?}", &filelist.path)
-                    }) {
-                        error!("{err:?}");
-                    }
+                if !removed.contains(&filelist.path) {
+                    removed.push(filelist.path.clone());
                 }
                 // and remove them from the vec
                 false
             }
         });
-
-        for (path, recursive) in paths {
-            if !self.cache.iter().any(|filelist| filelist.path == path) {
-                // Skip paths that don't exists and files
-                if !path.exists() || !path.is_dir() {
-                    continue;
-                }
-                let filelist = Filelist::new(&path, recursive);
-                let outdated = filelist.outdated.clone();
-                self.cache.push(filelist);
-                let ping_clone = event_loop_ping.clone();
+        // The displays using the removed directories might not exist anymore
+        self.unions.get_mut().clear();
+        for path in removed {
+            // Stop watching paths that have been removed, unless another filelist still uses them
+            // Check that it exists before
+            if path.exists() && !self.cache.iter().any(|filelist| filelist.path == path) {
                 if let Err(err) = hotwatch
-                    .watch(&path, move |event| match event.kind {
-                        hotwatch::EventKind::Create(_)
-                        | hotwatch::EventKind::Remove(_)
-                        | hotwatch::EventKind::Modify(_) => {
-                            // We could manually update the list of files with the information
-                            // we get here, but the inotify on linux is not reliable,
-                            // so we prefer to always trigger an update and just reload
-                            // the entire list
-                            // See: https://github.com/notify-rs/notify/issues/412
-                            outdated.store(true, Ordering::Release);
-                            ping_clone.ping();
-                        }
-                        _ => {}
-                    })
-                    .wrap_err_with(|| format!("Failed to watch for changes for file {:?}", &path))
+                    .unwatch(&path)
+                    .wrap_err_with(|| format!("Failed to unwatch changes for file {:?}", &path))
                 {
                     error!("{err:?}");
                 }
             }
         }
 
+        for (path, recursive, filter) in paths {
+            if self
+                .cache
+                .iter()
+                .any(|filelist| filelist.is_for(&path, recursive, &filter))
+            {
+                continue;
+            }
+            // Skip paths that don't exists and files
+            if !path.exists() || !path.is_dir() {
+                continue;
+            }
+            // The directory is already watched, share its outdated flag
+            if let Some(outdated) = self
+                .cache
+                .iter()
+                .find(|filelist| filelist.path == path)
+                .map(|filelist| filelist.outdated.clone())
+            {
+                self.cache
+                    .push(Filelist::new(&path, recursive, filter, outdated));
+                continue;
+            }
+            let outdated = Arc::new(AtomicBool::new(false));
+            self.cache
+                .push(Filelist::new(&path, recursive, filter, outdated.clone()));
+            let ping_clone = event_loop_ping.clone();
+            if let Err(err) = hotwatch
+                .watch(&path, move |event| match event.kind {
+                    hotwatch::EventKind::Create(_)
+                    | hotwatch::EventKind::Remove(_)
+                    | hotwatch::EventKind::Modify(_) => {
+                        // We could manually update the list of files with the information
+                        // we get here, but the inotify on linux is not reliable,
+                        // so we prefer to always trigger an update and just reload
+                        // the entire list
+                        // See: https://github.com/notify-rs/notify/issues/412
+                        outdated.store(true, Ordering::Release);
+                        ping_clone.ping();
+                    }
+                    _ => {}
+                })
+                .wrap_err_with(|| format!("Failed to watch for changes for file {:?}", &path))
+            {
+                error!("{err:?}");
+            }
+        }
+
         self.update_cache();
     }
 
     pub fn update_cache(&mut self) {
-        for filelist in &mut self.cache {
-            if filelist.outdated.load(std::sync::atomic::Ordering::Relaxed) {
+        // Read all the flags first, the filelists of the same directory share them
+        let outdated: Vec<bool> = self
+            .cache
+            .iter()
+            .map(|filelist| filelist.outdated.load(Ordering::Acquire))
+            .collect();
+        let unions = self.unions.get_mut();
+        for (filelist, outdated) in self.cache.iter_mut().zip(outdated) {
+            if outdated {
+                filelist.outdated.store(false, Ordering::Relaxed);
                 filelist.populate();
+                unions.retain(|union| !union.path.contains(&filelist.path));
             }
         }
     }
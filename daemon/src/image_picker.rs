@@ -7,11 +7,12 @@ use std::{
 
 use log::warn;
 use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, QueueHandle};
+use wpaperd_ipc::{GotoTarget, ImagePosition};
 
 use crate::{
     filelist_cache::FilelistCache,
     wallpaper_groups::{WallpaperGroup, WallpaperGroups},
-    wallpaper_info::{Recursive, Sorting, WallpaperInfo},
+    wallpaper_info::{Sorting, WallpaperInfo},
     wpaperd::Wpaperd,
 };
 
@@ -65,6 +66,11 @@ impl Queue {
         }
     }
 
+    /// Images in the order they have been drawn and the index of the current one
+    pub fn entries(&self) -> (Vec<PathBuf>, usize) {
+        (self.buffer.iter().cloned().collect(), self.current)
+    }
+
     fn is_full(&self) -> bool {
         self.buffer.len() == self.size
     }
@@ -121,6 +127,8 @@ impl Queue {
 enum ImagePickerAction {
     Next,
     Previous,
+    /// Show this image next, regardless of the sorting
+    Set(PathBuf),
 }
 
 struct GroupedRandom {
@@ -182,14 +190,7 @@ impl ImagePickerSorting {
                 ))
             }
             Some(Sorting::Ascending) => {
-                let files_len = filelist_cache
-                    .clone()
-                    .borrow()
-                    .get(
-                        &wallpaper_info.path,
-                        wallpaper_info.recursive.unwrap_or_default(),
-                    )
-                    .len();
+                let files_len = filelist_cache.clone().borrow().get(wallpaper_info).len();
                 Self::new_ascending(files_len)
             }
             Some(Sorting::Descending) => Self::new_descending(),
@@ -215,6 +216,8 @@ pub struct ImagePicker {
     sorting: ImagePickerSorting,
     filelist_cache: Rc<RefCell<FilelistCache>>,
     reload: bool,
+    /// Image set explicitly via IPC that replaces the configured path until it is unset
+    sticky_img: Option<PathBuf>,
 }
 
 impl ImagePicker {
@@ -236,11 +239,12 @@ impl ImagePicker {
             ),
             filelist_cache,
             reload: false,
+            sticky_img: None,
         }
     }
 
     /// Get the next image based on the sorting method
-    fn get_image_path(&mut self, files: &[PathBuf]) -> (usize, PathBuf) {
+    fn get_image_path(&mut self, files: &[PathBuf], weights: Option<&[f64]>) -> (usize, PathBuf) {
         match (&self.action, &mut self.sorting) {
             (
                 None,
@@ -266,14 +270,14 @@ impl ImagePicker {
                 (0, self.current_img.to_path_buf())
             }
             (None | Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
-                next_random_image(&self.current_img, queue, files)
+                next_random_image(&self.current_img, queue, files, weights)
             }
             (None | Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group)) => {
                 let mut group = group.group.borrow_mut();
                 if self.current_img == group.current_image {
                     // start loading a new image
                     let (index, path) =
-                        next_random_image(&self.current_img, &mut group.queue, files);
+                        next_random_image(&self.current_img, &mut group.queue, files, weights);
                     group.loading_image = Some((index, path.to_path_buf()));
                     (index, path)
                 } else {
@@ -339,37 +343,66 @@ impl ImagePicker {
                 let index = (index + 1) % files.len();
                 (index, files[index].to_path_buf())
             }
+            (Some(ImagePickerAction::Set(_)), _) => {
+                unreachable!("explicitly set images are handled in get_image_from_path")
+            }
         }
     }
 
     pub fn get_image_from_path(
         &mut self,
-        path: &Path,
-        recursive: &Option<Recursive>,
+        wallpaper_info: &WallpaperInfo,
     ) -> Option<(PathBuf, usize)> {
-        if path.is_dir() {
-            let files = self
-                .filelist_cache
-                .borrow()
-                .get(path, recursive.unwrap_or_default());
+        let path = &wallpaper_info.path;
+        if let Some(ImagePickerAction::Set(img_path)) = &self.action {
+            let img_path = img_path.clone();
+            // Keep the index in sync if the image is part of the directory, so that the sorting
+            // continues from there
+            let index = if path.is_rotating() {
+                let files = self.filelist_cache.borrow().get(wallpaper_info);
+                files.iter().position(|file| *file == img_path)
+            } else {
+                None
+            };
+            let index = index.unwrap_or_else(|| self.get_current_index());
+            return Some((img_path, index));
+        }
+
+        if let Some(sticky_img) = &self.sticky_img {
+            // The sticky image works like a static path
+            return if *sticky_img == self.current_img && !self.reload {
+                None
+            } else {
+                let sticky_img = sticky_img.clone();
+                Some((sticky_img, self.get_current_index()))
+            };
+        }
+
+        if path.is_rotating() {
+            let files = self.filelist_cache.borrow().get(wallpaper_info);
 
             // There are no images, forcefully break out of the loop
             if files.is_empty() {
-                warn!("Directory {path:?} does not contain any valid image files.");
+                warn!("Path {path} does not contain any valid image files.");
                 None
             } else {
-                let (index, img_path) = self.get_image_path(&files);
+                let weights = path.weights(&files);
+                let (index, img_path) = self.get_image_path(&files, weights.as_deref());
                 if img_path == self.current_img && !self.reload {
                     None
                 } else {
                     Some((img_path, index))
                 }
             }
-        } else if path == self.current_img && !self.reload {
-            None
         } else {
-            // path is not a directory, also it's not the current image or we need to reload
-            Some((path.to_path_buf(), 0))
+            match path.image() {
+                Some(image) if image != self.current_img || self.reload => {
+                    // path is not a directory, also it's not the current image or we need to
+                    // reload
+                    Some((image.to_path_buf(), 0))
+                }
+                _ => None,
+            }
         }
     }
 
@@ -378,6 +411,10 @@ impl ImagePicker {
             (Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
                 queue.push(img_path.clone());
             }
+            (Some(ImagePickerAction::Set(_)), ImagePickerSorting::Random(queue)) => {
+                queue.push(img_path.clone());
+                queue.set_current_to(&img_path);
+            }
             (None | Some(ImagePickerAction::Previous), ImagePickerSorting::Random { .. }) => {}
             (
                 None | Some(ImagePickerAction::Previous),
@@ -401,6 +438,15 @@ impl ImagePicker {
                 group.current_image.clone_from(&img_path);
                 group.index = index;
             }
+            (Some(ImagePickerAction::Set(_)), ImagePickerSorting::GroupedRandom(group)) => {
+                let mut group = group.group.borrow_mut();
+                let queue = &mut group.queue;
+                queue.push(img_path.clone());
+                queue.set_current_to(&img_path);
+                group.loading_image = None;
+                group.current_image.clone_from(&img_path);
+                group.index = index;
+            }
         }
 
         self.current_img = img_path;
@@ -413,15 +459,75 @@ impl ImagePicker {
     }
 
     /// Update wallpaper by going up 1 index through the cached image paths
-    pub fn next_image(&mut self, path: &Path, recursive: &Option<Recursive>) {
+    pub fn next_image(&mut self, wallpaper_info: &WallpaperInfo) {
         self.action = Some(ImagePickerAction::Next);
-        self.get_image_from_path(path, recursive);
+        self.get_image_from_path(wallpaper_info);
     }
 
     pub fn current_image(&self) -> PathBuf {
         self.current_img.clone()
     }
 
+    /// Show `img_path` as the next image. When `sticky` is true, the image will be kept
+    /// on screen until [`ImagePicker::unset_sticky_image`] is called, otherwise the
+    /// sorting continues as usual from this image.
+    pub fn set_image(&mut self, img_path: PathBuf, sticky: bool) {
+        self.sticky_img = if sticky { Some(img_path.clone()) } else { None };
+        if img_path != self.current_img {
+            self.action = Some(ImagePickerAction::Set(img_path));
+        }
+    }
+
+    /// Stop showing the sticky image; the configured path is used again
+    pub fn unset_sticky_image(&mut self) {
+        self.sticky_img = None;
+        // Keep the current image on screen until the next image is requested
+        self.action = None;
+    }
+
+    /// Where the picker is in the list of images
+    pub fn position(&self) -> ImagePosition {
+        match &self.sorting {
+            ImagePickerSorting::Random(_) | ImagePickerSorting::GroupedRandom(_) => {
+                let (entries, current) = self.history().expect("random sorting to have a queue");
+                ImagePosition::Queue { entries, current }
+            }
+            ImagePickerSorting::Ascending(index) | ImagePickerSorting::Descending(index) => {
+                ImagePosition::Index(*index)
+            }
+        }
+    }
+
+    /// Images drawn so far, from the oldest to the newest, and the index of the current one.
+    /// Only the random sortings keep track of them.
+    pub fn history(&self) -> Option<(Vec<PathBuf>, usize)> {
+        match &self.sorting {
+            ImagePickerSorting::Random(queue) => Some(queue.entries()),
+            ImagePickerSorting::GroupedRandom(grouped_random) => {
+                Some(grouped_random.group.borrow().queue.entries())
+            }
+            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => None,
+        }
+    }
+
+    /// Show the image at `index` of the history, the next images will be picked from there.
+    /// Return false if there is no such entry.
+    pub fn jump_to_history(&mut self, index: usize) -> bool {
+        let Some((mut entries, _)) = self.history() else {
+            return false;
+        };
+        if index >= entries.len() {
+            return false;
+        }
+        self.set_image(entries.swap_remove(index), false);
+        true
+    }
+
+    #[inline]
+    pub fn is_sticky(&self) -> bool {
+        self.sticky_img.is_some()
+    }
+
     /// Return true if the path changed
     pub fn update_sorting(
         &mut self,
@@ -439,13 +545,7 @@ impl ImagePicker {
                     if !path_changed => {}
                 (_, Sorting::Ascending) if path_changed => {
                     self.sorting = ImagePickerSorting::new_ascending(
-                        self.filelist_cache
-                            .borrow()
-                            .get(
-                                &wallpaper_info.path,
-                                wallpaper_info.recursive.unwrap_or_default(),
-                            )
-                            .len(),
+                        self.filelist_cache.borrow().get(wallpaper_info).len(),
                     );
                 }
                 (_, Sorting::Descending) if path_changed => {
@@ -563,6 +663,7 @@ fn next_random_image(
     current_image: &Path,
     queue: &mut Queue,
     files: &[PathBuf],
+    weights: Option<&[f64]>,
 ) -> (usize, PathBuf) {
     // Use the next images in the queue, if any
     while let Some((next, index)) = queue.next() {
@@ -580,7 +681,7 @@ fn next_random_image(
     // that the queue is bigger than the amount of available wallpapers
     let mut tries = 5;
     loop {
-        let index = fastrand::usize(..files.len());
+        let index = random_index(files.len(), weights);
         // search for an image that has not been drawn yet
         // fail after 5 tries
         if !queue.contains(&files[index]) {
@@ -592,7 +693,7 @@ fn next_random_image(
         // the current one. We also know that there is more than one image
         if tries == 0 {
             break loop {
-                let index = fastrand::usize(..files.len());
+                let index = random_index(files.len(), weights);
                 if files[index] != current_image {
                     break (index, files[index].to_path_buf());
                 }
@@ -603,6 +704,48 @@ fn next_random_image(
     }
 }
 
+/// Pick a random index up to `len`, each with a probability proportional to its weight
+fn random_index(len: usize, weights: Option<&[f64]>) -> usize {
+    let Some(weights) = weights else {
+        return fastrand::usize(..len);
+    };
+    let mut target = fastrand::f64() * weights.iter().sum::<f64>();
+    weights
+        .iter()
+        .position(|weight| {
+            if target < *weight {
+                true
+            } else {
+                target -= weight;
+                false
+            }
+        })
+        // Rounding errors can leave a bit of target after the last weight
+        .or_else(|| weights.iter().rposition(|weight| *weight > 0.0))
+        .unwrap_or(len - 1)
+}
+
+/// Find the image of `files` that `target` refers to.
+/// A file named exactly as `target` wins, otherwise the first file whose path contains it is
+/// returned, so that the name can include the subdirectory of the image.
+pub fn find_image(files: &[PathBuf], target: &GotoTarget) -> Option<PathBuf> {
+    match target {
+        GotoTarget::Index(index) => files.get(*index).cloned(),
+        GotoTarget::Name(name) => files
+            .iter()
+            .find(|path| {
+                path.file_name()
+                    .map_or(false, |file_name| file_name == name.as_str())
+            })
+            .or_else(|| {
+                files
+                    .iter()
+                    .find(|path| path.to_string_lossy().contains(name.as_str()))
+            })
+            .cloned(),
+    }
+}
+
 fn get_previous_image_for_random(current_image: &Path, queue: &mut Queue) -> (usize, PathBuf) {
     while let Some((prev, index)) = queue.previous() {
         if prev.exists() {
@@ -619,6 +762,7 @@ fn get_previous_image_for_random(current_image: &Path, queue: &mut Queue) -> (us
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
+    use crate::wallpaper_info::{PathSource, WallpaperPath};
 
     #[test]
     fn test_push() {
@@ -674,4 +818,121 @@ mod tests {
         assert_eq!(Some((Path::new("mypath8"), 1)), queue.next());
         assert_eq!(None, queue.next());
     }
+
+    #[test]
+    fn test_find_image() {
+        let files = vec![
+            PathBuf::from("/photos/trip-01.jpg"),
+            PathBuf::from("/photos/trip-02.jpg"),
+            PathBuf::from("/photos/trip.jpg"),
+        ];
+        assert_eq!(
+            Some(PathBuf::from("/photos/trip-02.jpg")),
+            find_image(&files, &GotoTarget::Index(1))
+        );
+        assert_eq!(None, find_image(&files, &GotoTarget::Index(3)));
+        // Exact matches win over the partial ones
+        assert_eq!(
+            Some(PathBuf::from("/photos/trip.jpg")),
+            find_image(&files, &GotoTarget::Name("trip.jpg".to_string()))
+        );
+        assert_eq!(
+            Some(PathBuf::from("/photos/trip-01.jpg")),
+            find_image(&files, &GotoTarget::Name("trip-".to_string()))
+        );
+        assert_eq!(
+            None,
+            find_image(&files, &GotoTarget::Name("videos".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_find_image_in_subdirectory() {
+        let files = vec![
+            PathBuf::from("/wallpapers/city/lake.jpg"),
+            PathBuf::from("/wallpapers/nature/lake.jpg"),
+            PathBuf::from("/wallpapers/nature/lake-sunset.jpg"),
+        ];
+        assert_eq!(
+            Some(PathBuf::from("/wallpapers/nature/lake.jpg")),
+            find_image(&files, &GotoTarget::Name("nature/lake".to_string()))
+        );
+        // The exact file name is preferred over the paths containing it
+        assert_eq!(
+            Some(PathBuf::from("/wallpapers/nature/lake-sunset.jpg")),
+            find_image(&files, &GotoTarget::Name("lake-sunset.jpg".to_string()))
+        );
+        assert_eq!(
+            Some(PathBuf::from("/wallpapers/city/lake.jpg")),
+            find_image(&files, &GotoTarget::Name("city/".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_weighted_random_index() {
+        let path = WallpaperPath::new(vec![
+            PathSource {
+                path: PathBuf::from("/walls/nature"),
+                weight: 1,
+            },
+            PathSource {
+                path: PathBuf::from("/walls/fav.png"),
+                weight: 3,
+            },
+        ]);
+        let files = vec![
+            PathBuf::from("/walls/fav.png"),
+            PathBuf::from("/walls/nature/lake.png"),
+            PathBuf::from("/walls/nature/sea.png"),
+        ];
+        let weights = path.weights(&files).unwrap();
+        assert_eq!(weights, vec![3.0, 0.5, 0.5]);
+        for _ in 0..100 {
+            assert!(random_index(files.len(), Some(&weights)) < files.len());
+        }
+        // Only the files with a weight can be picked
+        for _ in 0..100 {
+            assert_eq!(random_index(3, Some(&[0.0, 2.0, 0.0])), 1);
+        }
+    }
+
+    #[test]
+    fn test_weighted_distribution() {
+        // A directory with many images is picked as often as a single image with the same weight
+        let path = WallpaperPath::new(vec![
+            PathSource {
+                path: PathBuf::from("/walls/nature"),
+                weight: 2,
+            },
+            PathSource {
+                path: PathBuf::from("/walls/fav.png"),
+                weight: 2,
+            },
+        ]);
+        let mut files = vec![PathBuf::from("/walls/fav.png")];
+        files.extend((0..9).map(|i| PathBuf::from(format!("/walls/nature/{i}.png"))));
+        let weights = path.weights(&files).unwrap();
+
+        fastrand::seed(42);
+        let picks = 10_000;
+        let fav = (0..picks)
+            .filter(|_| random_index(files.len(), Some(&weights)) == 0)
+            .count();
+        assert!((4_500..5_500).contains(&fav), "fav.png picked {fav} times");
+    }
+
+    #[test]
+    fn test_set_current_to() {
+        let mut queue = Queue::with_capacity(3);
+        queue.push(PathBuf::from("mypath"));
+        queue.push(PathBuf::from("mypath2"));
+        queue.push(PathBuf::from("mypath3"));
+        queue.set_current_to(Path::new("mypath"));
+        assert_eq!(Path::new("mypath"), queue.current());
+        assert_eq!(Some((Path::new("mypath2"), 1)), queue.next());
+
+        let (entries, current) = queue.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(current, 1);
+    }
 }
@@ -1,37 +1,162 @@
 //! IPC socket server.
 //! Based on <https://github.com/catacombing/catacomb/blob/master/src/ipc_server.rs>
 
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::fs;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
-use color_eyre::eyre::{ensure, WrapErr};
-use color_eyre::{Result, Section};
+use color_eyre::eyre::{bail, eyre, WrapErr};
+use color_eyre::{Report, Result, Section};
+use log::{debug, error, info, warn};
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use nix::sys::stat::{umask, Mode as FileMode};
+use nix::unistd::getuid;
+use serde::Serialize;
+use smithay_client_toolkit::reexports::calloop::generic::Generic;
+use smithay_client_toolkit::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay_client_toolkit::reexports::calloop::{
+    Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+};
 use smithay_client_toolkit::reexports::client::QueueHandle;
-use wpaperd_ipc::{IpcError, IpcMessage, IpcResponse};
+use wpaperd_ipc::{
+    write_frame, Client, ClientError, ConfigDiagnostic, DisplayDetails, DisplayResult,
+    DisplayStatus, FrameDecoder, Handshake, ImagePosition, IpcError, IpcMessage, IpcResponse,
+    WallpaperDetails, WallpaperSource, PROTOCOL_VERSION,
+};
 
+use crate::config::{Config, IpcConfig};
+use crate::filelist_cache::is_image;
+use crate::image_picker::find_image;
 use crate::socket::SocketSource;
 use crate::surface::Surface;
+use crate::wallpaper_info::{Recursive, Sorting, WallpaperInfoOverrides};
 use crate::Wpaperd;
 
-/// Create an IPC socket.
-pub fn listen_on_ipc_socket(socket_path: &Path) -> Result<SocketSource> {
-    // Try to delete the socket if it exists already.
+/// How long a client can stay silent before the connection gets dropped
+const IPC_TIMEOUT: Duration = Duration::from_secs(1);
+/// How many bytes can wait for a client to read them before dropping it
+const MAX_IPC_OUTPUT: usize = 1024 * 1024;
+/// Maximum number of reads from a client before going back to the event loop
+const IPC_READS_PER_WAKEUP: usize = 16;
+/// Maximum number of clients connected at the same time, subscribers included
+const MAX_IPC_CLIENTS: usize = 32;
+/// How long we wait for another wpaperd to answer, or to exit when replacing it
+const RUNNING_DAEMON_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Create an IPC socket. When another wpaperd is listening on it, fail or, if `replace` is
+/// set, stop it first.
+pub fn listen_on_ipc_socket(
+    socket_path: &Path,
+    ipc_config: &IpcConfig,
+    replace: bool,
+) -> Result<SocketSource> {
     if socket_path.exists() {
+        stop_running_daemon(socket_path, replace)?;
+        // Nobody is listening on the socket anymore, it is stale
         fs::remove_file(socket_path)
             .wrap_err_with(|| format!("Failed to remove file {socket_path:?}"))?;
     }
 
     // Spawn unix socket event source.
-    let listener = UnixListener::bind(socket_path)
-        .wrap_err_with(|| format!("Failed to bind socket {socket_path:?}"))?;
+    // Only the owner can access the socket, from the moment it is created
+    let old_umask = umask(FileMode::from_bits_truncate(0o177));
+    let listener = UnixListener::bind(socket_path);
+    umask(old_umask);
+    let listener = listener.wrap_err_with(|| format!("Failed to bind socket {socket_path:?}"))?;
+    set_socket_permissions(socket_path, ipc_config)?;
     let socket = SocketSource::new(listener)
         .wrap_err_with(|| format!("Failed to create a socket in {socket_path:?}"))?;
     Ok(socket)
 }
 
+/// Check if a wpaperd is listening on the socket and, when `replace` is set, stop it.
+/// Return an error if it is running and has not been stopped.
+fn stop_running_daemon(socket_path: &Path, replace: bool) -> Result<()> {
+    let mut client = match Client::connect_to(socket_path, RUNNING_DAEMON_TIMEOUT) {
+        // Nobody is listening, the socket has been left behind
+        Err(ClientError::Connect { source, .. })
+            if matches!(
+                source.kind(),
+                ErrorKind::ConnectionRefused | ErrorKind::NotFound
+            ) =>
+        {
+            return Ok(());
+        }
+        Err(err) => {
+            return Err(Report::new(err))
+                .wrap_err_with(|| format!("Another process is listening on socket {socket_path:?}"))
+                .suggestion("Stop the wpaperd running on this display before starting a new one");
+        }
+        Ok(client) => client,
+    };
+
+    if !replace {
+        return Err(eyre!(
+            "wpaperd is already running on socket {socket_path:?}"
+        ))
+        .suggestion("Stop it with wpaperctl quit or start wpaperd with --replace");
+    }
+
+    info!("Replacing the wpaperd running on socket {socket_path:?}");
+    client
+        .shutdown()
+        .wrap_err("Failed to stop the running wpaperd")?;
+    // The socket stops accepting connections once the other wpaperd has exited
+    let deadline = Instant::now() + RUNNING_DAEMON_TIMEOUT;
+    while Instant::now() < deadline {
+        if UnixStream::connect(socket_path).is_err() {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    bail!("wpaperd running on socket {socket_path:?} did not exit after being asked to")
+}
+
+/// Let other users reach the socket only when the configuration allows them to connect,
+/// their credentials are then checked on every connection
+pub fn set_socket_permissions(socket_path: &Path, ipc_config: &IpcConfig) -> Result<()> {
+    let mode = if ipc_config.allows_others() {
+        // The default socket is in XDG_RUNTIME_DIR, that only its owner can access
+        let dir = socket_path.parent().unwrap_or(Path::new("/"));
+        match fs::metadata(dir) {
+            Ok(metadata) if metadata.permissions().mode() & 0o011 == 0 => warn!(
+                "Other users are allowed in the [ipc] section but they cannot reach the socket \
+                {socket_path:?}, {dir:?} is only accessible by its owner; pass --socket with a \
+                path in a directory they can access"
+            ),
+            _ => {}
+        }
+        0o666
+    } else {
+        0o600
+    };
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(mode))
+        .wrap_err_with(|| format!("Failed to set the permissions of socket {socket_path:?}"))
+}
+
+/// Only accept the clients run by the same user as wpaperd or allowed by the configuration.
+/// Return the user of the client.
+fn check_peer_credentials(stream: &UnixStream, ipc_config: &IpcConfig) -> Result<u32> {
+    let credentials = getsockopt(stream, PeerCredentials)
+        .wrap_err("Failed to get the credentials of the IPC client")?;
+    let (uid, gid) = (credentials.uid(), credentials.gid());
+    if uid != getuid().as_raw() && !ipc_config.allows(uid, gid) {
+        return Err(eyre!(
+            "Rejected IPC connection from user {uid} (pid {})",
+            credentials.pid()
+        ))
+        .suggestion("Add the user to allowed-users in the [ipc] section of the configuration");
+    }
+    Ok(uid)
+}
+
 fn check_monitors(wpaperd: &Wpaperd, monitors: &Vec<String>) -> Result<(), IpcError> {
     for monitor in monitors {
         if !wpaperd
@@ -61,35 +186,456 @@ fn collect_surfaces(wpaperd: &mut Wpaperd, monitors: Vec<String>) -> Vec<&mut Su
         .collect()
 }
 
-/// Handle IPC socket messages.
-pub fn handle_message(
+/// Run `f` on each display and collect its outcome, so that a failing display doesn't prevent
+/// the others from being updated
+fn for_each_display(
+    wpaperd: &mut Wpaperd,
+    monitors: Vec<String>,
+    mut f: impl FnMut(&mut Surface) -> Result<()>,
+) -> IpcResponse {
+    IpcResponse::DisplayResults {
+        results: collect_surfaces(wpaperd, monitors)
+            .into_iter()
+            .map(|surface| DisplayResult {
+                display: surface.name().to_string(),
+                error: f(surface).err().map(|err| report_to_string(&err)),
+            })
+            .collect(),
+    }
+}
+
+/// Format a report on a single line, without the colors meant for the terminal
+fn report_to_string(report: &Report) -> String {
+    let report = format!("{report:#}");
+    let mut res = String::with_capacity(report.len());
+    let mut chars = report.chars().peekable();
+    while let Some(c) = chars.next() {
+        // The messages are colored with SGR sequences, i.e. `\x1b[1;35m`
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+            while chars.next_if(|c| c.is_ascii_digit() || *c == ';').is_some() {}
+            chars.next_if_eq(&'m');
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+/// Format the error of a file that is not the configuration in use, leaving out the lines
+/// quoted by the toml parser, as the file might not be meant to be read by the client
+fn config_error_to_string(report: &Report) -> String {
+    match report
+        .chain()
+        .find_map(|err| err.downcast_ref::<toml::de::Error>())
+    {
+        Some(err) => format!("{report}: {}", err.message().trim()),
+        None => report_to_string(report),
+    }
+}
+
+/// Cargo features wpaperd has been built with
+fn enabled_features() -> Vec<String> {
+    [
+        ("avif", cfg!(feature = "avif")),
+        ("dbus", cfg!(feature = "dbus")),
+        ("jemalloc", cfg!(feature = "jemalloc")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature.to_string())
+    .collect()
+}
+
+fn display_details(surface: &Surface) -> DisplayDetails {
+    let display_info = surface.display_info();
+    let wallpaper_info = &surface.wallpaper_info;
+    DisplayDetails {
+        name: display_info.name.clone(),
+        description: display_info.description.clone(),
+        make: display_info.make.clone(),
+        model: display_info.model.clone(),
+        serial: display_info.serial().to_string(),
+        width: display_info.width,
+        height: display_info.height,
+        scale: display_info.scale,
+        transform: display_info.transform_name().to_string(),
+        section: surface.config_section.clone(),
+        wallpaper: WallpaperDetails {
+            path: wallpaper_info
+                .path
+                .sources()
+                .iter()
+                .map(|source| WallpaperSource {
+                    path: source.path.clone(),
+                    weight: source.weight,
+                })
+                .collect(),
+            mode: wallpaper_info.mode.to_string(),
+            sorting: wallpaper_info.sorting.map(|sorting| sorting.to_string()),
+            duration: wallpaper_info.duration,
+            transition: wallpaper_info.transition.name(),
+            transition_time: wallpaper_info.transition_time,
+            group: match wallpaper_info.sorting {
+                Some(Sorting::GroupedRandom { group }) => Some(group),
+                _ => None,
+            },
+            offset: wallpaper_info.offset,
+            queue_size: wallpaper_info.drawn_images_queue_size,
+            recursive: wallpaper_info.recursive.unwrap_or_default() == Recursive::On,
+            include: wallpaper_info.filter.include().to_vec(),
+            exclude: wallpaper_info.filter.exclude().to_vec(),
+            exec: wallpaper_info.exec.clone(),
+        },
+        overrides: surface.overrides.to_ipc(),
+        current_image: surface.image_picker.current_image(),
+        paused: surface.should_pause(),
+        has_context: surface.has_context(),
+    }
+}
+
+/// Bytes waiting to be written to an IPC client.
+///
+/// The streams are non-blocking: what the client does not read right away is kept here and
+/// written by the `writer` source once the stream becomes writable, so that a slow client
+/// never stalls the event loop.
+pub struct Output {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+    /// Source waiting for the stream to be writable, enabled only while `buffer` is not empty
+    writer: Option<RegistrationToken>,
+    ev_handle: LoopHandle<'static, Wpaperd>,
+    /// Set when the client stopped reading, the stream failed or the connection has been
+    /// closed: nothing is written anymore and the connection is dropped
+    closed: bool,
+}
+
+impl Output {
+    fn new(stream: UnixStream, ev_handle: LoopHandle<'static, Wpaperd>) -> Self {
+        Self {
+            stream,
+            buffer: Vec::new(),
+            writer: None,
+            ev_handle,
+            closed: false,
+        }
+    }
+
+    /// Queue a frame, writing as much as possible right away
+    pub fn push<T: Serialize>(&mut self, value: &T) {
+        if self.closed {
+            return;
+        }
+        if self.buffer.len() > MAX_IPC_OUTPUT {
+            warn!("IPC client is not reading what wpaperd sends, dropping it");
+            self.close();
+            return;
+        }
+        if let Err(err) = write_frame(&mut self.buffer, value) {
+            error!("Failed to encode a frame for the IPC client: {err}");
+            return;
+        }
+        match self.flush() {
+            Ok(true) => {}
+            Ok(false) => {
+                if let Some(writer) = &self.writer {
+                    if let Err(err) = self.ev_handle.enable(writer) {
+                        error!("Failed to wait for the IPC client to be writable: {err}");
+                        self.close();
+                    }
+                }
+            }
+            Err(err) => {
+                debug!("Failed to write to the IPC client: {err}");
+                self.close();
+            }
+        }
+    }
+
+    /// Write the queued bytes until the stream would block.
+    /// Return true when everything has been written.
+    fn flush(&mut self) -> std::io::Result<bool> {
+        while !self.buffer.is_empty() {
+            match (&self.stream).write(&self.buffer) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.buffer.drain(..n);
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+        self.buffer = Vec::new();
+    }
+}
+
+/// State of a connected IPC client, shared between its sources and its idle timer
+struct Connection {
+    /// User running the client
+    peer_uid: u32,
+    decoder: FrameDecoder,
+    handshake_done: bool,
+    /// The client only receives events from now on
+    subscribed: bool,
+    last_activity: Instant,
+    output: Rc<RefCell<Output>>,
+    source: Option<RegistrationToken>,
+    timer: Option<RegistrationToken>,
+}
+
+impl Connection {
+    fn new(peer_uid: u32, output: Output) -> Self {
+        Self {
+            peer_uid,
+            decoder: FrameDecoder::new(),
+            handshake_done: false,
+            subscribed: false,
+            last_activity: Instant::now(),
+            output: Rc::new(RefCell::new(output)),
+            source: None,
+            timer: None,
+        }
+    }
+
+    /// Read what the client sent and answer the requests received entirely.
+    /// Return false when the connection must be closed.
+    fn process(
+        &mut self,
+        stream: &UnixStream,
+        qh: &QueueHandle<Wpaperd>,
+        ev_handle: &LoopHandle<Wpaperd>,
+        wpaperd: &mut Wpaperd,
+    ) -> Result<bool> {
+        let mut reader = stream;
+        let mut buf = [0; 4096];
+        let mut closed = false;
+        // Do not keep reading forever from a client that writes faster than we decode, the
+        // socket source is level triggered and we will be called again
+        for _ in 0..IPC_READS_PER_WAKEUP {
+            match reader.read(&mut buf) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) => self.decoder.push(&buf[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err).wrap_err("Failed to read from the IPC stream"),
+            }
+        }
+        self.last_activity = Instant::now();
+
+        if !self.handshake_done {
+            let handshake: Handshake = match self
+                .decoder
+                .next_frame()
+                .wrap_err("Failed to read the handshake from the IPC stream")?
+            {
+                Some(handshake) => handshake,
+                None => return Ok(!closed),
+            };
+            if handshake.version != PROTOCOL_VERSION {
+                let resp: Result<Handshake, IpcError> = Err(IpcError::VersionMismatch {
+                    client: handshake.version,
+                    daemon: PROTOCOL_VERSION,
+                });
+                self.output.borrow_mut().push(&resp);
+                bail!(
+                    "IPC client uses protocol version {} but wpaperd uses version {PROTOCOL_VERSION}",
+                    handshake.version
+                );
+            }
+            self.output
+                .borrow_mut()
+                .push(&Ok::<_, IpcError>(Handshake::new()));
+            self.handshake_done = true;
+        }
+
+        while let Some(message) = self
+            .decoder
+            .next_frame::<IpcMessage>()
+            .wrap_err("Failed to read a message from the IPC stream")?
+        {
+            if self.subscribed {
+                bail!("IPC subscriber sent a message");
+            }
+            if let IpcMessage::Subscribe = message {
+                self.output
+                    .borrow_mut()
+                    .push(&Ok::<_, IpcError>(IpcResponse::Subscribed));
+                // From now on the connection is only used to push events
+                self.subscribed = true;
+                wpaperd.subscribers.borrow_mut().add(self.output.clone());
+                continue;
+            }
+
+            let resp = handle_request(message, self.peer_uid, qh, ev_handle, wpaperd);
+            self.output.borrow_mut().push(&resp);
+        }
+
+        if self.output.borrow().is_closed() {
+            bail!("IPC client is not reading the responses");
+        }
+        if closed && self.decoder.has_pending_data() {
+            bail!("IPC client closed the connection in the middle of a message");
+        }
+        Ok(!closed)
+    }
+
+    /// Remove the sources of the connection from the event loop
+    fn close(&mut self, ev_handle: &LoopHandle<Wpaperd>, wpaperd: &mut Wpaperd) {
+        if let Some(token) = self.source.take() {
+            ev_handle.remove(token);
+        }
+        if let Some(token) = self.timer.take() {
+            ev_handle.remove(token);
+        }
+        let mut output = self.output.borrow_mut();
+        if let Some(token) = output.writer.take() {
+            ev_handle.remove(token);
+        }
+        // The subscribers drop it on the next event
+        output.close();
+        wpaperd.ipc_clients -= 1;
+    }
+}
+
+/// Handle a new IPC connection.
+///
+/// The client starts with a [`Handshake`] frame, then it can send any number of
+/// [`IpcMessage`], each one answered with a `Result<IpcResponse, IpcError>` frame.
+/// Every connection gets its own sources in the event loop, so that a slow client never
+/// blocks the rendering, and it is dropped after being idle for [`IPC_TIMEOUT`]; the
+/// subscribers are dropped when they stop reading their events instead.
+pub fn handle_connection(
     ustream: UnixStream,
     qh: QueueHandle<Wpaperd>,
+    ev_handle: &LoopHandle<'static, Wpaperd>,
     wpaperd: &mut Wpaperd,
 ) -> Result<()> {
-    const SIZE: usize = 4096;
-    let mut buffer = [0; SIZE];
-
-    // Read new content to buffer.
-    let mut stream = BufReader::new(&ustream);
-    let n = stream
-        .read(&mut buffer)
-        .wrap_err("Failed to read data from the IPC stream")?;
-    // The message is empty
-    if n == 0 {
+    let peer_uid = check_peer_credentials(&ustream, &wpaperd.config.ipc)?;
+    if wpaperd.ipc_clients >= MAX_IPC_CLIENTS {
+        warn!("Too many IPC clients connected, dropping the new connection");
         return Ok(());
     }
-    ensure!(
-        n != SIZE,
-        "The message received was bigger than the buffer size"
-    );
 
-    // Read pending events on socket.
-    let message: IpcMessage = serde_json::from_slice(&buffer[..n])
-        .wrap_err_with(|| format!("Failed to deserialize message {:?}", &buffer[..n]))?;
+    ustream
+        .set_nonblocking(true)
+        .wrap_err("Failed to set the IPC stream as non-blocking")?;
+    let output_stream = ustream
+        .try_clone()
+        .wrap_err("Failed to clone the IPC stream")?;
+    let writer_stream = ustream
+        .try_clone()
+        .wrap_err("Failed to clone the IPC stream")?;
+
+    let connection = Rc::new(RefCell::new(Connection::new(
+        peer_uid,
+        Output::new(output_stream, ev_handle.clone()),
+    )));
+    let source = ev_handle
+        .insert_source(Generic::new(ustream, Interest::READ, Mode::Level), {
+            let connection = connection.clone();
+            let ev_handle = ev_handle.clone();
+            move |_, stream, wpaperd| {
+                let mut connection = connection.borrow_mut();
+                match connection.process(stream, &qh, &ev_handle, wpaperd) {
+                    Ok(true) => return Ok(PostAction::Continue),
+                    Ok(false) => {}
+                    Err(err) => error!("{err:?}"),
+                }
+                // The source is removed by returning PostAction::Remove
+                connection.source = None;
+                connection.close(&ev_handle, wpaperd);
+                Ok(PostAction::Remove)
+            }
+        })
+        .map_err(|e| eyre!("{e}"))
+        .wrap_err("Failed to insert the IPC connection into the event loop")?;
+
+    let writer = ev_handle
+        .insert_source(Generic::new(writer_stream, Interest::WRITE, Mode::Level), {
+            let connection = connection.clone();
+            let ev_handle = ev_handle.clone();
+            move |_, _, wpaperd| {
+                let mut connection = connection.borrow_mut();
+                let res = connection.output.borrow_mut().flush();
+                match res {
+                    // Wait until there is something else to write
+                    Ok(true) => return Ok(PostAction::Disable),
+                    Ok(false) => {
+                        connection.last_activity = Instant::now();
+                        return Ok(PostAction::Continue);
+                    }
+                    Err(err) => debug!("Failed to write to the IPC client: {err}"),
+                }
+                // The source is removed by returning PostAction::Remove
+                connection.output.borrow_mut().writer = None;
+                connection.close(&ev_handle, wpaperd);
+                Ok(PostAction::Remove)
+            }
+        })
+        .map_err(|e| eyre!("{e}"))
+        .wrap_err("Failed to insert the IPC connection writer into the event loop")?;
+    ev_handle
+        .disable(&writer)
+        .map_err(|e| eyre!("{e}"))
+        .wrap_err("Failed to disable the IPC connection writer")?;
+
+    let timer = ev_handle
+        .insert_source(Timer::from_duration(IPC_TIMEOUT), {
+            let connection = connection.clone();
+            let ev_handle = ev_handle.clone();
+            move |_, _, wpaperd| {
+                let mut connection = connection.borrow_mut();
+                let idle = connection.last_activity.elapsed();
+                if connection.output.borrow().is_closed() {
+                    debug!("Dropping IPC client not reading what wpaperd sends");
+                } else if connection.subscribed {
+                    // Subscribers never send anything, only check that they keep reading
+                    return TimeoutAction::ToDuration(IPC_TIMEOUT);
+                } else if idle < IPC_TIMEOUT {
+                    return TimeoutAction::ToDuration(IPC_TIMEOUT - idle);
+                } else {
+                    debug!("Dropping idle IPC client");
+                }
+                // The timer is removed by returning TimeoutAction::Drop
+                connection.timer = None;
+                connection.close(&ev_handle, wpaperd);
+                TimeoutAction::Drop
+            }
+        })
+        .map_err(|e| eyre!("{e}"))
+        .wrap_err("Failed to insert the IPC connection timer into the event loop")?;
+
+    let mut connection = connection.borrow_mut();
+    connection.source = Some(source);
+    connection.timer = Some(timer);
+    connection.output.borrow_mut().writer = Some(writer);
+    wpaperd.ipc_clients += 1;
+    Ok(())
+}
 
-    // Handle IPC events.
-    let resp: Result<IpcResponse, IpcError> = match message {
+/// Execute a single [`IpcMessage`] sent by the user `peer_uid` and return the response for
+/// the client
+pub fn handle_request(
+    message: IpcMessage,
+    peer_uid: u32,
+    qh: &QueueHandle<Wpaperd>,
+    ev_handle: &LoopHandle<Wpaperd>,
+    wpaperd: &mut Wpaperd,
+) -> Result<IpcResponse, IpcError> {
+    match message {
         IpcMessage::CurrentWallpaper { monitor } => wpaperd
             .surfaces
             .iter()
@@ -97,6 +643,17 @@ pub fn handle_message(
             .map(|surface| surface.image_picker.current_image())
             .map(|path| IpcResponse::CurrentWallpaper { path })
             .ok_or(IpcError::MonitorNotFound { monitor }),
+        IpcMessage::Ping => Ok(IpcResponse::Pong),
+        IpcMessage::Version => Ok(IpcResponse::Version {
+            daemon: env!("CARGO_PKG_VERSION").to_string(),
+            protocol: PROTOCOL_VERSION,
+            features: enabled_features(),
+        }),
+        IpcMessage::Shutdown => {
+            // The main loop checks this flag after the event loop has been dispatched
+            wpaperd.should_exit.store(true, Ordering::Release);
+            Ok(IpcResponse::Ok)
+        }
         IpcMessage::AllWallpapers => Ok(IpcResponse::AllWallpapers {
             entries: wpaperd
                 .surfaces
@@ -112,35 +669,27 @@ pub fn handle_message(
 
         IpcMessage::PreviousWallpaper { monitors } => {
             check_monitors(wpaperd, &monitors).map(|_| {
-                for surface in collect_surfaces(wpaperd, monitors) {
+                for_each_display(wpaperd, monitors, |surface| {
                     surface.image_picker.previous_image();
-                    surface.load_new_wallpaper();
-                }
-
-                IpcResponse::Ok
+                    surface.try_load_new_wallpaper()
+                })
             })
         }
 
         IpcMessage::NextWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
-            for surface in collect_surfaces(wpaperd, monitors) {
-                surface.image_picker.next_image(
-                    &surface.wallpaper_info.path,
-                    &surface.wallpaper_info.recursive,
-                );
-                surface.load_new_wallpaper();
-            }
-
-            IpcResponse::Ok
+            for_each_display(wpaperd, monitors, |surface| {
+                surface.image_picker.next_image(&surface.wallpaper_info);
+                surface.try_load_new_wallpaper()
+            })
         }),
 
         IpcMessage::ReloadWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
-            for surface in collect_surfaces(wpaperd, monitors) {
+            for_each_display(wpaperd, monitors, |surface| {
                 surface.image_picker.reload();
-                surface.load_new_wallpaper();
-                surface.queue_draw(&qh);
-            }
-
-            IpcResponse::Ok
+                let res = surface.try_load_new_wallpaper();
+                surface.queue_draw(qh);
+                res
+            })
         }),
 
         IpcMessage::PauseWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
@@ -171,23 +720,315 @@ pub fn handle_message(
             check_monitors(wpaperd, &monitors).map(|_| IpcResponse::DisplaysStatus {
                 entries: collect_surfaces(wpaperd, monitors)
                     .iter()
-                    .map(|surface| {
-                        (
-                            surface.name().to_string(),
-                            surface.status().to_string(),
-                            surface.get_remaining_duration(),
-                        )
+                    .map(|surface| DisplayStatus {
+                        display: surface.name().to_string(),
+                        status: surface.status().to_string(),
+                        duration_left: surface.get_remaining_duration(),
+                        overrides: surface.overrides.to_ipc(),
                     })
                     .collect(),
             })
         }
-    };
 
-    let mut stream = BufWriter::new(ustream);
-    stream
-        .write_all(&serde_json::to_vec(&resp).unwrap())
-        .wrap_err("Failed to write response to the IPC client")
-        .suggestion("The client might have died, try running it again")?;
+        IpcMessage::SetWallpaper {
+            monitors,
+            path,
+            sticky,
+        } => check_monitors(wpaperd, &monitors).and_then(|_| {
+            if !path.is_file() {
+                return Err(IpcError::ImageNotFound { path });
+            }
+            // A sticky file that cannot be loaded would be retried on every tick
+            if !is_image(&path) {
+                return Err(IpcError::NotAnImage { path });
+            }
+            Ok(for_each_display(wpaperd, monitors, |surface| {
+                surface.image_picker.set_image(path.clone(), sticky);
+                surface.try_load_new_wallpaper()
+            }))
+        }),
+
+        IpcMessage::UnsetWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
+            for_each_display(wpaperd, monitors, |surface| {
+                if surface.image_picker.is_sticky() {
+                    surface.image_picker.unset_sticky_image();
+                    surface.try_load_new_wallpaper()
+                } else {
+                    Ok(())
+                }
+            })
+        }),
 
-    Ok(())
+        IpcMessage::SetOverrides {
+            monitors,
+            overrides,
+            until_reload,
+        } => check_monitors(wpaperd, &monitors).and_then(|_| {
+            let overrides =
+                WallpaperInfoOverrides::from_ipc(overrides, until_reload).map_err(|err| {
+                    IpcError::InvalidOverride {
+                        reason: format!("{err:#}"),
+                    }
+                })?;
+            let surfaces = collect_surfaces(wpaperd, monitors);
+            if overrides.duration.is_some() || overrides.sorting.is_some() {
+                if let Some(surface) = surfaces
+                    .iter()
+                    .find(|surface| !surface.wallpaper_info.path.is_rotating())
+                {
+                    return Err(IpcError::InvalidOverride {
+                        reason: format!(
+                            "duration and sorting can only be changed when path is a directory, \
+                            which is not the case for display {}",
+                            surface.name()
+                        ),
+                    });
+                }
+            }
+            for surface in surfaces {
+                surface.overrides.merge(overrides.clone());
+            }
+            wpaperd.update_surfaces(ev_handle.clone(), qh);
+
+            Ok(IpcResponse::Ok)
+        }),
+
+        IpcMessage::ClearOverrides { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
+            for surface in collect_surfaces(wpaperd, monitors) {
+                surface.overrides = WallpaperInfoOverrides::default();
+            }
+            wpaperd.update_surfaces(ev_handle.clone(), qh);
+
+            IpcResponse::Ok
+        }),
+
+        IpcMessage::ListDisplays { monitors } => {
+            check_monitors(wpaperd, &monitors).map(|_| IpcResponse::Displays {
+                displays: collect_surfaces(wpaperd, monitors)
+                    .iter()
+                    .map(|surface| display_details(surface))
+                    .collect(),
+            })
+        }
+
+        IpcMessage::ListImages { monitor } => wpaperd
+            .surfaces
+            .iter()
+            .find(|surface| surface.name() == monitor)
+            .map(|surface| {
+                let path = &surface.wallpaper_info.path;
+                if path.is_rotating() {
+                    IpcResponse::Images {
+                        files: wpaperd
+                            .filelist_cache
+                            .borrow()
+                            .get(&surface.wallpaper_info)
+                            .to_vec(),
+                        position: surface.image_picker.position(),
+                    }
+                } else {
+                    IpcResponse::Images {
+                        files: path.paths().map(Path::to_path_buf).collect(),
+                        position: ImagePosition::Static,
+                    }
+                }
+            })
+            .ok_or(IpcError::MonitorNotFound { monitor }),
+
+        IpcMessage::GetHistory { monitor } => match wpaperd
+            .surfaces
+            .iter()
+            .find(|surface| surface.name() == monitor)
+        {
+            Some(surface) => surface
+                .image_picker
+                .history()
+                .map(|(entries, current)| IpcResponse::History { entries, current })
+                .ok_or(IpcError::NoHistory { monitor }),
+            None => Err(IpcError::MonitorNotFound { monitor }),
+        },
+
+        IpcMessage::JumpToHistory { monitor, index } => match wpaperd.surface_from_name(&monitor) {
+            Some(surface) => match surface.image_picker.history() {
+                Some((entries, _)) if index < entries.len() => {
+                    if !entries[index].is_file() {
+                        return Err(IpcError::ImageNotFound {
+                            path: entries[index].clone(),
+                        });
+                    }
+                    let res = if surface.image_picker.jump_to_history(index) {
+                        surface.try_load_new_wallpaper()
+                    } else {
+                        Ok(())
+                    };
+                    Ok(IpcResponse::DisplayResults {
+                        results: vec![DisplayResult {
+                            display: monitor,
+                            error: res.err().map(|err| report_to_string(&err)),
+                        }],
+                    })
+                }
+                Some((entries, _)) => Err(IpcError::InvalidHistoryIndex {
+                    index,
+                    len: entries.len(),
+                }),
+                None => Err(IpcError::NoHistory { monitor }),
+            },
+            None => Err(IpcError::MonitorNotFound { monitor }),
+        },
+
+        IpcMessage::GotoImage { monitors, target } => {
+            check_monitors(wpaperd, &monitors).and_then(|_| {
+                let filelist_cache = wpaperd.filelist_cache.clone();
+                // Resolve the image for every display first, so that nothing changes on error
+                let mut images = Vec::new();
+                for surface in collect_surfaces(wpaperd, monitors) {
+                    let path = &surface.wallpaper_info.path;
+                    let image = if path.is_rotating() {
+                        let files = filelist_cache.borrow().get(&surface.wallpaper_info);
+                        find_image(&files, &target)
+                    } else if let [source] = path.sources() {
+                        find_image(std::slice::from_ref(&source.path), &target)
+                    } else {
+                        None
+                    };
+                    match image {
+                        Some(image) => images.push((surface, image)),
+                        None => {
+                            return Err(IpcError::NoMatchingImage {
+                                monitor: surface.name().to_string(),
+                                target,
+                            })
+                        }
+                    }
+                }
+                let results = images
+                    .into_iter()
+                    .map(|(surface, image)| {
+                        surface.image_picker.set_image(image, false);
+                        DisplayResult {
+                            display: surface.name().to_string(),
+                            error: surface
+                                .try_load_new_wallpaper()
+                                .err()
+                                .map(|err| report_to_string(&err)),
+                        }
+                    })
+                    .collect();
+
+                Ok(IpcResponse::DisplayResults { results })
+            })
+        }
+
+        IpcMessage::ReloadConfig { path } => {
+            // wpaperd would read any file it has access to on behalf of the other users
+            if path.is_some() && peer_uid != getuid().as_raw() {
+                return Err(IpcError::PermissionDenied {
+                    reason: "only the user running wpaperd can load another configuration file"
+                        .to_string(),
+                });
+            }
+            let is_config = path
+                .as_ref()
+                .map_or(true, |path| *path == wpaperd.config.path);
+            let path = path.unwrap_or_else(|| wpaperd.config.path.clone());
+            // The drop-in files only apply to the default configuration
+            let conf_d = if is_config {
+                wpaperd.config.conf_d.as_deref()
+            } else {
+                None
+            };
+            let (mut config, diagnostics) =
+                Config::load(&path, conf_d).map_err(|err| IpcError::InvalidConfig {
+                    reason: if is_config {
+                        report_to_string(&err)
+                    } else {
+                        config_error_to_string(&err)
+                    },
+                })?;
+            diagnostics.log();
+            config.reloaded.clone_from(&wpaperd.config.reloaded);
+            wpaperd.config = config;
+            wpaperd.config_changed = true;
+
+            let to_ipc = |entries: Vec<(String, Report)>| {
+                entries
+                    .into_iter()
+                    .map(|(section, report)| ConfigDiagnostic {
+                        section,
+                        message: report_to_string(&report),
+                    })
+                    .collect()
+            };
+            Ok(IpcResponse::ConfigReloaded {
+                errors: to_ipc(diagnostics.errors),
+                warnings: to_ipc(diagnostics.warnings),
+            })
+        }
+
+        IpcMessage::Subscribe => unreachable!("subscriptions are handled in Connection::process"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::owo_colors::OwoColorize;
+    use wpaperd_ipc::IpcEvent;
+
+    use super::*;
+
+    #[test]
+    fn test_report_to_string() {
+        let report = Err::<(), _>(eyre!("file not found"))
+            .wrap_err_with(|| format!("Failed to load {}", "DP-3".bold().magenta()))
+            .unwrap_err();
+        assert_eq!(
+            report_to_string(&report),
+            "Failed to load DP-3: file not found"
+        );
+    }
+
+    #[test]
+    fn test_config_error_to_string() {
+        let path = std::env::temp_dir().join(format!("wpaperd-secret-{}", std::process::id()));
+        fs::write(&path, "password = hunter2\n").unwrap();
+        let report = Config::load(&path, None).err().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let reason = config_error_to_string(&report);
+        assert!(reason.starts_with("Failed to parse the configuration in "));
+        assert!(!reason.contains("hunter2"), "{reason}");
+        assert!(report_to_string(&report).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_output_queue() {
+        use smithay_client_toolkit::reexports::calloop::EventLoop;
+
+        let event_loop = EventLoop::<Wpaperd>::try_new().unwrap();
+        let (stream, mut client) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut output = Output::new(stream, event_loop.handle());
+
+        // A client that reads receives everything
+        output.push(&IpcEvent::ConfigReloaded);
+        let mut decoder = FrameDecoder::new();
+        let mut buf = [0; 4096];
+        let n = client.read(&mut buf).unwrap();
+        decoder.push(&buf[..n]);
+        assert!(matches!(
+            decoder.next_frame::<IpcEvent>().unwrap(),
+            Some(IpcEvent::ConfigReloaded)
+        ));
+
+        // A client that stops reading is dropped instead of blocking wpaperd
+        let event = IpcEvent::WallpaperChanged {
+            monitor: "DP-3".to_string(),
+            path: PathBuf::from("/".repeat(64 * 1024)),
+        };
+        while !output.is_closed() {
+            output.push(&event);
+        }
+        assert!(output.buffer.is_empty());
+    }
 }
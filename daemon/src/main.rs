@@ -1,4 +1,6 @@
 mod config;
+#[cfg(feature = "dbus")]
+mod dbus;
 mod display_info;
 mod filelist_cache;
 mod image_loader;
@@ -7,6 +9,7 @@ mod ipc_server;
 mod opts;
 mod render;
 mod socket;
+mod subscribers;
 mod surface;
 mod wallpaper_groups;
 mod wallpaper_info;
@@ -19,6 +22,7 @@ use std::{
     fs::File,
     io::Write,
     os::fd::FromRawFd,
+    path::{Path, PathBuf},
     process::exit,
     rc::Rc,
     sync::{
@@ -36,7 +40,7 @@ use filelist_cache::FilelistCache;
 use flexi_logger::{Duplicate, FileSpec, Logger};
 use hotwatch::Hotwatch;
 use image_loader::ImageLoader;
-use ipc_server::{handle_message, listen_on_ipc_socket};
+use ipc_server::{handle_connection, listen_on_ipc_socket, set_socket_permissions};
 use log::error;
 use nix::unistd::fork;
 use opts::Opts;
@@ -46,7 +50,7 @@ use smithay_client_toolkit::reexports::{
     client::{globals::registry_queue_init, Connection, Proxy},
 };
 use wallpaper_info::Sorting;
-use wpaperd_ipc::socket_path;
+use wpaperd_ipc::{socket_path, IpcEvent};
 use xdg::BaseDirectories;
 
 use crate::wpaperd::Wpaperd;
@@ -58,37 +62,87 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
-fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
+fn config_file_path(config: Option<PathBuf>, xdg_dirs: &BaseDirectories) -> Result<PathBuf> {
     // Path passed from the CLI or the wpaperd.toml file has precedence
-    let config_file = if let Some(config) = opts.config {
-        config
+    if let Some(config) = config {
+        Ok(config)
     } else {
         // Read the new config or the legacy file
         if let Some(legacy_config_file) = xdg_dirs.find_config_file("wallpaper.toml") {
-            legacy_config_file
+            Ok(legacy_config_file)
         } else if let Some(config_file) = xdg_dirs.find_config_file("config.toml") {
-            config_file
+            Ok(config_file)
         } else {
-            return Err(
+            Err(
                 eyre!("No configuration file found at wallpaper.toml or config.toml")
                     .wrap_err("Failed to locate any config file"),
-            );
+            )
         }
-    };
+    }
+}
+
+/// Directory of the drop-in configuration files. They are only read along the default
+/// configuration, a file passed explicitly is used alone.
+fn conf_d_dir(config: Option<&Path>, xdg_dirs: &BaseDirectories) -> Option<PathBuf> {
+    match config {
+        Some(_) => None,
+        None => Some(xdg_dirs.get_config_home().join("conf.d")),
+    }
+}
+
+/// Validate the configuration without connecting to the compositor and report every problem
+/// found. Returns true if the configuration is valid.
+fn check_config(config_file: &Path, conf_d: Option<&Path>) -> Result<bool> {
+    let (config, mut diagnostics) = Config::load(config_file, conf_d)
+        .wrap_err_with(|| format!("Failed to read the configuration at {config_file:?}"))?;
+    config.check_paths(&mut diagnostics);
+
+    for (section, err) in &diagnostics.warnings {
+        eprintln!("warning in [{section}]: {err:?}\n");
+    }
+    for (section, err) in &diagnostics.errors {
+        eprintln!("error in [{section}]: {err:?}\n");
+    }
+    if diagnostics.errors.is_empty() {
+        println!("Configuration {} is valid", config_file.to_string_lossy());
+        Ok(true)
+    } else {
+        eprintln!(
+            "Configuration {} has {} invalid section(s)",
+            config_file.to_string_lossy(),
+            diagnostics.errors.len()
+        );
+        Ok(false)
+    }
+}
+
+fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
+    let conf_d = conf_d_dir(opts.config.as_deref(), &xdg_dirs);
+    let config_file = config_file_path(opts.config, &xdg_dirs)?;
 
     let reloaded = Arc::new(AtomicBool::new(false));
     // Do not stop when the configuration is invalid, we can always reload it at runtime
-    let mut config = match Config::new_from_path(&config_file) {
+    let mut config = match Config::new_from_path(&config_file, conf_d.as_deref()) {
         Ok(config) => config,
         Err(err) => {
             error!("{err:?}");
             let mut config = Config::default();
             config.path = config_file;
+            config.conf_d = conf_d;
             config
         }
     };
     config.reloaded = Some(reloaded);
 
+    // Start listening on the IPC socket before touching the displays, so that we don't fight
+    // with another wpaperd over them
+    let ipc_socket = match opts.socket {
+        Some(socket) => socket,
+        None => socket_path().wrap_err("Failed to locate wpaperd socket")?,
+    };
+    let socket = listen_on_ipc_socket(&ipc_socket, &config.ipc, opts.replace)
+        .wrap_err("Failed to listen to IPC socket")?;
+
     // we use the OpenGL ES API because it's more widely supported
     // and it's used by wlroots
     egl.bind_api(egl::OPENGL_ES_API)
@@ -109,14 +163,14 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         registry_queue_init(&conn).wrap_err("Failed to initialize the Wayland registry queue")?;
     let qh = event_queue.handle();
 
-    let mut event_loop = calloop::EventLoop::<Wpaperd>::try_new()?;
+    let mut event_loop = calloop::EventLoop::<'static, Wpaperd>::try_new()?;
 
     WaylandSource::new(conn.clone(), event_queue)
         .insert(event_loop.handle())
         .map_err(|e| eyre!("{e}"))
         .wrap_err("Failed to insert the Wayland source into the event loop")?;
 
-    let (ping, ping_source) = calloop::ping::make_ping()
+    let (config_ping, ping_source) = calloop::ping::make_ping()
         .wrap_err("Failed to create a calloop::ping::Ping for the hotwatch listener")?;
     event_loop
         .handle()
@@ -126,8 +180,9 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
 
     let mut hotwatch = Hotwatch::new().wrap_err("Failed to initialize hotwatch listener")?;
     config
-        .listen_to_changes(&mut hotwatch, ping)
+        .listen_to_changes(&mut hotwatch, config_ping.clone())
         .wrap_err("Failed to watch on config file changes")?;
+    let mut watched_config_paths = config.watched_paths();
 
     let (ping, filelist_cache) =
         FilelistCache::new(config.paths(), &mut hotwatch, event_loop.handle())
@@ -170,19 +225,23 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
     )
     .wrap_err("Failed to initiliaze wpaperd status")?;
 
-    // Start listening on the IPC socket
-    let socket = listen_on_ipc_socket(&socket_path().wrap_err("Failed to locate wpaperd socket")?)
-        .wrap_err("Failed to listen to IPC socket")?;
-
     // Add source to calloop loop.
+    let ev_handle = event_loop.handle();
+    let qh_clone = qh.clone();
     event_loop
         .handle()
-        .insert_source(socket, |stream, _, wpaperd| {
-            if let Err(err) = handle_message(stream, qh.clone(), wpaperd) {
+        .insert_source(socket, move |stream, _, wpaperd| {
+            if let Err(err) = handle_connection(stream, qh_clone.clone(), &ev_handle, wpaperd) {
                 error!("{err:?}");
             }
         })?;
 
+    // The IPC socket is still available without the session bus, go on
+    #[cfg(feature = "dbus")]
+    if let Err(err) = dbus::start(qh.clone(), &event_loop.handle(), &wpaperd) {
+        error!("{err:?}");
+    }
+
     if let Some(notify) = opts.notify {
         let mut f = unsafe { File::from_raw_fd(notify as i32) };
         if let Err(err) = writeln!(f) {
@@ -193,7 +252,7 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
 
     let (ctrlc_ping, ctrl_ping_source) =
         calloop::ping::make_ping().wrap_err("Failed to create calloop::")?;
-    let should_exit = Arc::new(AtomicBool::new(false));
+    let should_exit = wpaperd.should_exit.clone();
     let should_exit_clone = should_exit.clone();
     // Handle SIGINT, SIGTERM, and SIGHUP, so that the application can stop nicely
     ctrlc::set_handler(move || {
@@ -216,14 +275,32 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         }
 
         // If the config has been modified, this value will return true
-        if wpaperd
+        let config_changed = wpaperd
             .config
             .reloaded
             .as_ref()
             .unwrap()
             .load(Ordering::Acquire)
-            && wpaperd.config.update()
-        {
+            && wpaperd.config.update();
+        // The config could also have been reloaded by an IPC request
+        if config_changed || std::mem::take(&mut wpaperd.config_changed) {
+            // The IPC request can read the configuration from another file, and the included
+            // files might have changed
+            if wpaperd.config.watched_paths() != watched_config_paths {
+                for path in &watched_config_paths {
+                    if let Err(err) = hotwatch.unwatch(path) {
+                        error!("Failed to stop watching {path:?}: {err:?}");
+                    }
+                }
+                if let Err(err) = wpaperd
+                    .config
+                    .listen_to_changes(&mut hotwatch, config_ping.clone())
+                {
+                    error!("{err:?}");
+                }
+                watched_config_paths = wpaperd.config.watched_paths();
+            }
+
             // Update the filelist cache, keep it up to date
             // We need to call this before because updating the surfaces
             // will start loading the wallpapers in the background
@@ -233,8 +310,19 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
                 ping.clone(),
             );
 
+            // Other users might have been allowed to connect
+            if let Err(err) = set_socket_permissions(&ipc_socket, &wpaperd.config.ipc) {
+                error!("{err:?}");
+            }
+
             // Read the config, update the paths in the surfaces
+            wpaperd.drop_overrides_until_reload();
             wpaperd.update_surfaces(event_loop.handle(), &qh);
+
+            wpaperd
+                .subscribers
+                .borrow_mut()
+                .broadcast(IpcEvent::ConfigReloaded);
         }
 
         // Due to how LayerSurface works, we cannot attach the egl window right away.
@@ -285,6 +373,16 @@ fn main() -> Result<()> {
 
     let opts = Opts::parse();
 
+    if let Some(path) = opts.check_config {
+        let path = path.or(opts.config);
+        let conf_d = conf_d_dir(path.as_deref(), &xdg_dirs);
+        let config_file = config_file_path(path, &xdg_dirs)?;
+        if !check_config(&config_file, conf_d.as_deref())? {
+            exit(1);
+        }
+        return Ok(());
+    }
+
     let mut logger = Logger::try_with_env_or_str(if opts.verbose { "debug" } else { "info" })
         .wrap_err("Failed to initialize logger")?;
 
@@ -309,3 +407,23 @@ fn main() -> Result<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conf_d_dir() {
+        let xdg_dirs = BaseDirectories::with_prefix("wpaperd").unwrap();
+        assert_eq!(
+            conf_d_dir(None, &xdg_dirs),
+            Some(xdg_dirs.get_config_home().join("conf.d"))
+        );
+        assert!(conf_d_dir(None, &xdg_dirs)
+            .unwrap()
+            .ends_with("wpaperd/conf.d"));
+        // --config, --check-config and ReloadConfig with a file outside of the configuration
+        // directory do not merge the drop-ins found next to it
+        assert_eq!(conf_d_dir(Some(Path::new("/tmp/x.toml")), &xdg_dirs), None);
+    }
+}
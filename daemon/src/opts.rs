@@ -30,4 +30,23 @@ pub struct Opts {
         help = "Readiness fd used by wpaperd to signal that it has started correctly"
     )]
     pub notify: Option<u8>,
+    #[clap(
+        long,
+        help = "Path of the IPC socket ($XDG_RUNTIME_DIR/wpaperd-<instance>.sock by default, \
+                <instance> being the file name of $WAYLAND_DISPLAY)"
+    )]
+    pub socket: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Stop the wpaperd already running on the same socket and take its place"
+    )]
+    pub replace: bool,
+    #[clap(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        help = "Validate the configuration (the one in use by default) without starting wpaperd, \
+        then exit with an error if any problem has been found"
+    )]
+    pub check_config: Option<Option<PathBuf>>,
 }
@@ -91,6 +91,18 @@ impl UniformSetter for [f32; 4] {
     }
 }
 
+/// Convert a variant name to the kebab-case name used by serde
+fn kebab_case(name: &str) -> String {
+    let mut res = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i != 0 {
+            res.push('-');
+        }
+        res.push(c.to_ascii_lowercase());
+    }
+    res
+}
+
 macro_rules! transition_shader {
     ($enum:ident { $($variant:ident { $($field_name:ident: $field_ty:ty = ($glsl_name:literal, $default_value:expr)),* } => $default_time:expr),* }) => {
         #[derive(Deserialize, Clone, Debug, PartialEq)]
@@ -130,6 +142,13 @@ macro_rules! transition_shader {
                     $($enum::$variant { .. } => $default_time,)*
                 }
             }
+
+            /// Name of the transition, as used in the configuration file
+            pub fn name(&self) -> String {
+                kebab_case(match self {
+                    $($enum::$variant { .. } => stringify!($variant),)*
+                })
+            }
         }
     };
 }
@@ -0,0 +1,46 @@
+//! IPC clients subscribed to the wpaperd events.
+
+use std::{cell::RefCell, rc::Rc};
+
+use wpaperd_ipc::IpcEvent;
+
+use crate::ipc_server::Output;
+
+/// Callback receiving every event, used by the services running beside the IPC socket
+pub type Listener = Box<dyn Fn(&IpcEvent)>;
+
+pub struct Subscribers {
+    outputs: Vec<Rc<RefCell<Output>>>,
+    listeners: Vec<Listener>,
+}
+
+impl Subscribers {
+    pub fn new() -> Self {
+        Self {
+            outputs: Vec::new(),
+            listeners: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, output: Rc<RefCell<Output>>) {
+        self.outputs.push(output);
+    }
+
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn add_listener(&mut self, listener: Listener) {
+        self.listeners.push(listener);
+    }
+
+    /// Queue the event for all the subscribers, dropping the ones whose connection is closed.
+    /// The events are written when the subscribers are ready to read them, the ones that stop
+    /// reading are disconnected.
+    pub fn broadcast(&mut self, event: IpcEvent) {
+        for listener in &self.listeners {
+            listener(&event);
+        }
+        self.outputs.retain(|output| !output.borrow().is_closed());
+        for output in &self.outputs {
+            output.borrow_mut().push(&event);
+        }
+    }
+}
@@ -10,7 +10,7 @@ use std::{
 use std::process::Command;
 
 use color_eyre::{
-    eyre::{eyre, OptionExt, WrapErr},
+    eyre::{ensure, eyre, OptionExt, WrapErr},
     Result,
 };
 use log::{error, warn};
@@ -33,14 +33,16 @@ use smithay_client_toolkit::{
         WaylandSurface,
     },
 };
+use wpaperd_ipc::IpcEvent;
 
 use crate::{
     display_info::DisplayInfo,
     image_loader::ImageLoader,
     image_picker::ImagePicker,
     render::EglContext,
+    subscribers::Subscribers,
     wallpaper_groups::WallpaperGroups,
-    wallpaper_info::{Sorting, WallpaperInfo},
+    wallpaper_info::{Sorting, WallpaperInfo, WallpaperInfoOverrides},
     wpaperd::Wpaperd,
 };
 
@@ -65,8 +67,13 @@ pub struct Surface {
     pub image_picker: ImagePicker,
     event_source: EventSource,
     pub wallpaper_info: WallpaperInfo,
+    /// Settings changed via IPC, applied on top of the configuration
+    pub overrides: WallpaperInfoOverrides,
+    /// Section of the configuration used for this display
+    pub config_section: String,
     display_info: DisplayInfo,
     image_loader: Rc<RefCell<ImageLoader>>,
+    subscribers: Rc<RefCell<Subscribers>>,
     window_drawn: bool,
     pub loading_image: Option<(PathBuf, usize)>,
     loading_image_tries: u8,
@@ -133,9 +140,12 @@ impl Surface {
             image_picker,
             event_source: EventSource::NotSet,
             wallpaper_info,
+            overrides: WallpaperInfoOverrides::default(),
+            config_section: String::new(),
             window_drawn: false,
             should_pause: false,
             image_loader: wpaperd.image_loader.clone(),
+            subscribers: wpaperd.subscribers.clone(),
             loading_image: None,
             loading_image_tries: 0,
             skip_next_transition: first_transition,
@@ -221,10 +231,7 @@ impl Surface {
     pub fn load_wallpaper(&mut self) -> Result<bool> {
         // If we were not already trying to load an image
         if self.loading_image.is_none() {
-            if let Some(item) = self.image_picker.get_image_from_path(
-                &self.wallpaper_info.path,
-                &self.wallpaper_info.recursive.clone(),
-            ) {
+            if let Some(item) = self.image_picker.get_image_from_path(&self.wallpaper_info) {
                 if self.image_picker.current_image() == item.0 && !self.image_picker.is_reloading()
                 {
                     return Ok(true);
@@ -279,14 +286,22 @@ impl Surface {
                 Ok(false)
             }
             crate::image_loader::ImageLoaderStatus::Error => {
+                self.subscribers
+                    .borrow_mut()
+                    .broadcast(IpcEvent::ImageLoadFailed {
+                        monitor: self.name().to_string(),
+                        path: image_path.clone(),
+                    });
                 // We don't want to try too many times
                 self.loading_image_tries += 1;
                 // The image we were trying to load failed
                 self.loading_image = None;
                 // If we have tried too many times, stop
-                if self.loading_image_tries != 5 {
+                if self.loading_image_tries < 5 {
                     return self.load_wallpaper();
                 }
+                // Give the next image another 5 tries
+                self.loading_image_tries = 0;
                 Ok(false)
             }
         }
@@ -325,6 +340,12 @@ impl Surface {
         };
 
         self.update_wallpaper_link(&image_path);
+        self.subscribers
+            .borrow_mut()
+            .broadcast(IpcEvent::WallpaperChanged {
+                monitor: self.name().to_string(),
+                path: image_path.clone(),
+            });
         self.image_picker.update_current_image(image_path, index);
         self.get_context()
             .unwrap()
@@ -340,10 +361,6 @@ impl Surface {
         &self.display_info.name
     }
 
-    pub fn description(&self) -> &str {
-        &self.display_info.description
-    }
-
     /// Resize the surface
     pub fn resize(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<()> {
         // self.layer.set_size(width as u32, height as u32);
@@ -454,11 +471,11 @@ impl Surface {
 
         // Put the new value in place
         std::mem::swap(&mut self.wallpaper_info, &mut wallpaper_info);
-        // if the two paths are different and the new path is a directory but doesn't contain the
-        // old image
+        // if the two paths are different and the new path is a directory or a list of paths but
+        // doesn't contain the old ones
         let path_changed = self.wallpaper_info.path != wallpaper_info.path
-            && self.wallpaper_info.path.is_dir()
-                && !wallpaper_info.path.starts_with(&self.wallpaper_info.path)
+            && self.wallpaper_info.path.is_rotating()
+                && !wallpaper_info.path.paths().all(|path| self.wallpaper_info.path.contains(path))
             // and the recursive mode is different
             && wallpaper_info.recursive.as_ref().zip(self.wallpaper_info.recursive.as_ref()).map(|(x, y)| x != y).unwrap_or(false);
         self.image_picker.update_sorting(
@@ -469,8 +486,7 @@ impl Surface {
         );
         if path_changed {
             // ask the image_picker to pick a new a image
-            self.image_picker
-                .next_image(&self.wallpaper_info.path, &self.wallpaper_info.recursive);
+            self.image_picker.next_image(&self.wallpaper_info);
             self.load_new_wallpaper();
         } else if let Some(Sorting::GroupedRandom { .. }) = self.wallpaper_info.sorting {
             // Always queue draw to load changes (needed for GroupedRandom)
@@ -579,10 +595,7 @@ impl Surface {
                         let saturating_sub = new_duration.saturating_sub(time_passed);
                         if saturating_sub.is_zero() {
                             // The image was on screen for the same time as the new duration
-                            self.image_picker.next_image(
-                                &self.wallpaper_info.path,
-                                &self.wallpaper_info.recursive,
-                            );
+                            self.image_picker.next_image(&self.wallpaper_info);
                             if let Err(err) = self.load_wallpaper().wrap_err_with(|| {
                                 format!(
                                     "Failed to query the image loader for display {}",
@@ -689,10 +702,7 @@ impl Surface {
                                         context.renderer.transition_finished();
                                     }
                                 }
-                                surface.image_picker.next_image(
-                                    &surface.wallpaper_info.path,
-                                    &surface.wallpaper_info.recursive,
-                                );
+                                surface.image_picker.next_image(&surface.wallpaper_info);
                                 surface.load_new_wallpaper();
                                 surface.wallpaper_info.duration.unwrap()
                             };
@@ -740,15 +750,29 @@ impl Surface {
         self.wl_surface.commit();
     }
 
+    /// Start loading the new wallpaper, returning why it cannot be shown on this display.
+    /// The image is decoded in the background: a success only means that the load started,
+    /// an image failing afterwards is reported with [`IpcEvent::ImageLoadFailed`].
+    pub fn try_load_new_wallpaper(&mut self) -> Result<()> {
+        self.load_wallpaper()
+            .wrap_err("Failed to query the image loader")?;
+        ensure!(
+            self.has_context(),
+            "EGL context is not available, the wallpaper will be drawn once it is recreated"
+        );
+        Ok(())
+    }
+
     #[inline]
     pub fn load_new_wallpaper(&mut self) {
-        if let Err(err) = self.load_wallpaper().wrap_err_with(|| {
-            format!(
-                "Failed to query the image loader for display {}",
-                self.name()
-            )
-        }) {
-            warn!("{err:?}");
+        if let Err(err) = self.try_load_new_wallpaper() {
+            warn!(
+                "{:?}",
+                err.wrap_err(format!(
+                    "Failed to load a new wallpaper for display {}",
+                    self.name()
+                ))
+            );
         }
     }
 
@@ -757,14 +781,24 @@ impl Surface {
     /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
     #[inline]
     pub fn pause(&mut self) {
-        self.should_pause = true;
+        if !self.should_pause {
+            self.should_pause = true;
+            self.subscribers.borrow_mut().broadcast(IpcEvent::Paused {
+                monitor: self.name().to_string(),
+            });
+        }
     }
     /// Indicate to the main event loop that the automatic wallpaper sequence for this [`Surface`]
     /// should be resumed.
     /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
     #[inline]
     pub fn resume(&mut self) {
-        self.should_pause = false;
+        if self.should_pause {
+            self.should_pause = false;
+            self.subscribers.borrow_mut().broadcast(IpcEvent::Resumed {
+                monitor: self.name().to_string(),
+            });
+        }
     }
 
     /// Toggle the pause state for this [`Surface`], which is responsible for indicating to the main
@@ -786,6 +820,15 @@ impl Surface {
         self.should_pause
     }
 
+    pub fn display_info(&self) -> &DisplayInfo {
+        &self.display_info
+    }
+
+    #[inline]
+    pub fn has_context(&self) -> bool {
+        self.context.is_some()
+    }
+
     pub fn wl_surface(&self) -> &wl_surface::WlSurface {
         &self.wl_surface
     }
@@ -799,7 +842,9 @@ impl Surface {
     }
 
     pub fn status(&self) -> &'static str {
-        if self.wallpaper_info.path.is_dir() {
+        if self.image_picker.is_sticky() {
+            "sticky"
+        } else if self.wallpaper_info.path.is_rotating() {
             if self.should_pause {
                 "paused"
             } else {
@@ -1,6 +1,15 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    cmp::Ordering,
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use color_eyre::{eyre::WrapErr, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde::Deserialize;
+use serde_json::{Map, Value};
+use wpaperd_ipc::WallpaperOverrides;
 
 use crate::{image_picker::ImagePicker, render::Transition};
 
@@ -21,9 +30,169 @@ impl From<bool> for Recursive {
     }
 }
 
+/// Glob patterns selecting the images found in the directories, matched against their path
+/// relative to the directory
+#[derive(Debug, Clone, Default)]
+pub struct ImageFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    include_set: GlobSet,
+    exclude_set: GlobSet,
+}
+
+impl ImageFilter {
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Result<Self> {
+        Ok(Self {
+            include_set: build_glob_set(&include)?,
+            exclude_set: build_glob_set(&exclude)?,
+            include,
+            exclude,
+        })
+    }
+
+    pub fn include(&self) -> &[String] {
+        &self.include
+    }
+
+    pub fn exclude(&self) -> &[String] {
+        &self.exclude
+    }
+
+    /// True if the file should be listed: it matches one of the include patterns, if any, and
+    /// none of the exclude patterns
+    pub fn is_included(&self, relative_path: &Path) -> bool {
+        (self.include.is_empty() || self.include_set.is_match(relative_path))
+            && !self.exclude_set.is_match(relative_path)
+    }
+
+    /// True if the directory must not be walked
+    pub fn is_excluded(&self, relative_path: &Path) -> bool {
+        self.exclude_set.is_match(relative_path)
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).wrap_err_with(|| format!("Invalid glob {pattern:?}"))?);
+    }
+    builder
+        .build()
+        .wrap_err("Failed to build the glob patterns")
+}
+
+// The compiled patterns are derived from the strings, compare only the latter
+impl PartialEq for ImageFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.include == other.include && self.exclude == other.exclude
+    }
+}
+
+impl Eq for ImageFilter {}
+
+impl PartialOrd for ImageFilter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ImageFilter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.include, &self.exclude).cmp(&(&other.include, &other.exclude))
+    }
+}
+
+/// One of the paths set for a display, either an image or a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathSource {
+    pub path: PathBuf,
+    /// How likely its images are to be picked by the random sortings, compared to the images
+    /// of the other paths
+    pub weight: u32,
+}
+
+/// Images and directories the wallpapers of a display are picked from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WallpaperPath(Vec<PathSource>);
+
+impl WallpaperPath {
+    pub fn new(sources: Vec<PathSource>) -> Self {
+        Self(sources)
+    }
+
+    pub fn sources(&self) -> &[PathSource] {
+        &self.0
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.0.iter().map(|source| source.path.as_path())
+    }
+
+    /// The image to show when a single file is set, `None` otherwise
+    pub fn image(&self) -> Option<&Path> {
+        match self.0.as_slice() {
+            [source] if !source.path.is_dir() => Some(&source.path),
+            _ => None,
+        }
+    }
+
+    /// True when the wallpaper is picked from a directory or from a list of paths
+    pub fn is_rotating(&self) -> bool {
+        !self.0.is_empty() && self.image().is_none()
+    }
+
+    /// True if `path` is one of the paths or is inside one of the directories
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths().any(|source| path.starts_with(source))
+    }
+
+    /// Weight of each of the `files`, `None` when there is a single path.
+    /// The weight of a path is split among the images it contributes, so that each path is
+    /// picked as often as its weight says, regardless of how many images it contains
+    pub fn weights(&self, files: &[PathBuf]) -> Option<Vec<f64>> {
+        if self.0.len() <= 1 {
+            return None;
+        }
+        let sources: Vec<Option<usize>> = files
+            .iter()
+            .map(|file| {
+                self.0
+                    .iter()
+                    .position(|source| file.starts_with(&source.path))
+            })
+            .collect();
+        let mut counts = vec![0u32; self.0.len()];
+        for source in sources.iter().flatten() {
+            counts[*source] += 1;
+        }
+        Some(
+            sources
+                .iter()
+                .map(|source| {
+                    source.map_or(0.0, |source| {
+                        f64::from(self.0[source].weight) / f64::from(counts[source])
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+impl fmt::Display for WallpaperPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, path) in self.paths().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct WallpaperInfo {
-    pub path: PathBuf,
+    pub path: WallpaperPath,
     pub duration: Option<Duration>,
     pub apply_shadow: bool,
     pub sorting: Option<Sorting>,
@@ -43,13 +212,15 @@ pub struct WallpaperInfo {
 
     /// Recursively iterate the directory set as path
     pub recursive: Option<Recursive>,
+    /// Images of the directories to use
+    pub filter: ImageFilter,
     pub exec: Option<PathBuf>,
 }
 
 impl Default for WallpaperInfo {
     fn default() -> Self {
         Self {
-            path: PathBuf::new(),
+            path: WallpaperPath::default(),
             duration: None,
             apply_shadow: false,
             sorting: None,
@@ -60,6 +231,7 @@ impl Default for WallpaperInfo {
             transition: Transition::Fade {},
             offset: None,
             recursive: None,
+            filter: ImageFilter::default(),
             exec: None,
         }
     }
@@ -78,6 +250,17 @@ pub enum Sorting {
     Descending,
 }
 
+impl fmt::Display for Sorting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            // The group is reported separately
+            Sorting::Random | Sorting::GroupedRandom { .. } => "random",
+            Sorting::Ascending => "ascending",
+            Sorting::Descending => "descending",
+        })
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum BackgroundMode {
@@ -88,3 +271,188 @@ pub enum BackgroundMode {
     Tile,
     FitBorderColor,
 }
+
+impl fmt::Display for BackgroundMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BackgroundMode::Stretch => "stretch",
+            BackgroundMode::Center => "center",
+            BackgroundMode::Fit => "fit",
+            BackgroundMode::Tile => "tile",
+            BackgroundMode::FitBorderColor => "fit-border-color",
+        })
+    }
+}
+
+/// Value of a setting changed at runtime
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Override<T> {
+    pub value: T,
+    /// Drop the value the next time the configuration is reloaded
+    pub until_reload: bool,
+}
+
+impl<T> Override<T> {
+    fn new(value: T, until_reload: bool) -> Self {
+        Self {
+            value,
+            until_reload,
+        }
+    }
+}
+
+/// Settings changed at runtime via IPC, applied on top of the [`WallpaperInfo`] generated from
+/// the configuration
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WallpaperInfoOverrides {
+    pub duration: Option<Override<Duration>>,
+    pub mode: Option<Override<BackgroundMode>>,
+    pub offset: Option<Override<f32>>,
+    pub transition: Option<Override<Transition>>,
+    pub sorting: Option<Override<Sorting>>,
+}
+
+impl WallpaperInfoOverrides {
+    pub fn from_ipc(overrides: WallpaperOverrides, until_reload: bool) -> Result<Self> {
+        // Reuse the deserialization of the configuration file, so that the values are the same
+        let mode = overrides
+            .mode
+            .map(|mode| {
+                serde_json::from_value(Value::String(mode.clone()))
+                    .wrap_err_with(|| format!("Invalid mode {mode}"))
+            })
+            .transpose()?;
+        let sorting = overrides
+            .sorting
+            .map(|sorting| {
+                serde_json::from_value(Value::String(sorting.clone()))
+                    .wrap_err_with(|| format!("Invalid sorting {sorting}"))
+            })
+            .transpose()?;
+        let transition = overrides
+            .transition
+            .map(|transition| {
+                // Transitions are tables in the configuration file, use their default settings
+                let mut map = Map::new();
+                map.insert(transition.clone(), Value::Object(Map::new()));
+                serde_json::from_value(Value::Object(map))
+                    .wrap_err_with(|| format!("Invalid transition {transition}"))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            duration: overrides
+                .duration
+                .map(|duration| Override::new(duration, until_reload)),
+            mode: mode.map(|mode| Override::new(mode, until_reload)),
+            offset: overrides
+                .offset
+                .map(|offset| Override::new(offset, until_reload)),
+            transition: transition.map(|transition| Override::new(transition, until_reload)),
+            sorting: sorting.map(|sorting| Override::new(sorting, until_reload)),
+        })
+    }
+
+    pub fn to_ipc(&self) -> WallpaperOverrides {
+        WallpaperOverrides {
+            duration: self.duration.map(|duration| duration.value),
+            mode: self.mode.map(|mode| mode.value.to_string()),
+            offset: self.offset.map(|offset| offset.value),
+            transition: self
+                .transition
+                .as_ref()
+                .map(|transition| transition.value.name()),
+            sorting: self.sorting.map(|sorting| sorting.value.to_string()),
+        }
+    }
+
+    /// Add the values set in `other`, keeping the ones that it doesn't set
+    pub fn merge(&mut self, other: Self) {
+        self.duration = other.duration.or(self.duration);
+        self.mode = other.mode.or(self.mode);
+        self.offset = other.offset.or(self.offset);
+        self.transition = other.transition.or(self.transition.take());
+        self.sorting = other.sorting.or(self.sorting);
+    }
+
+    /// Drop the values that should only last until the configuration is reloaded
+    pub fn drop_until_reload(&mut self) {
+        fn clear<T>(value: &mut Option<Override<T>>) {
+            if value.as_ref().map_or(false, |value| value.until_reload) {
+                *value = None;
+            }
+        }
+        clear(&mut self.duration);
+        clear(&mut self.mode);
+        clear(&mut self.offset);
+        clear(&mut self.transition);
+        clear(&mut self.sorting);
+    }
+
+    pub fn apply(&self, wallpaper_info: &mut WallpaperInfo) {
+        if let Some(duration) = self.duration {
+            wallpaper_info.duration = Some(duration.value);
+        }
+        if let Some(mode) = self.mode {
+            wallpaper_info.mode = mode.value;
+        }
+        if let Some(offset) = self.offset {
+            wallpaper_info.offset = Some(offset.value);
+        }
+        if let Some(Override {
+            value: transition, ..
+        }) = &self.transition
+        {
+            // Keep a custom transition-time, otherwise use the default of the new transition
+            if wallpaper_info.transition_time == wallpaper_info.transition.default_transition_time()
+            {
+                wallpaper_info.transition_time = transition.default_transition_time();
+            }
+            wallpaper_info.transition = transition.clone();
+        }
+        match (
+            self.sorting.map(|sorting| sorting.value),
+            wallpaper_info.sorting,
+        ) {
+            // Random sorting in a group is still random, do not leave the group
+            (Some(Sorting::Random), Some(Sorting::GroupedRandom { .. })) => {}
+            (Some(sorting), _) => wallpaper_info.sorting = Some(sorting),
+            (None, _) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_overrides_until_reload() {
+        let mut overrides = WallpaperInfoOverrides::from_ipc(
+            WallpaperOverrides {
+                mode: Some("fit".to_string()),
+                ..Default::default()
+            },
+            false,
+        )
+        .unwrap();
+        overrides.merge(
+            WallpaperInfoOverrides::from_ipc(
+                WallpaperOverrides {
+                    offset: Some(0.5),
+                    ..Default::default()
+                },
+                true,
+            )
+            .unwrap(),
+        );
+
+        // Only the values set until the reload are dropped
+        overrides.drop_until_reload();
+        assert_eq!(
+            overrides.mode.map(|mode| mode.value),
+            Some(BackgroundMode::Fit)
+        );
+        assert_eq!(overrides.offset, None);
+    }
+}
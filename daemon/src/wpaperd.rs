@@ -1,5 +1,7 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 use color_eyre::eyre::eyre;
 use color_eyre::owo_colors::OwoColorize;
@@ -20,12 +22,14 @@ use smithay_client_toolkit::{
     delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
     registry_handlers,
 };
+use wpaperd_ipc::IpcEvent;
 use xdg::BaseDirectories;
 
 use crate::config::Config;
 use crate::display_info::DisplayInfo;
 use crate::filelist_cache::FilelistCache;
 use crate::image_loader::ImageLoader;
+use crate::subscribers::Subscribers;
 use crate::surface::Surface;
 use crate::wallpaper_groups::WallpaperGroups;
 use crate::wallpaper_info::WallpaperInfo;
@@ -42,6 +46,14 @@ pub struct Wpaperd {
     pub filelist_cache: Rc<RefCell<FilelistCache>>,
     pub image_loader: Rc<RefCell<ImageLoader>>,
     pub wallpaper_groups: Rc<RefCell<WallpaperGroups>>,
+    pub subscribers: Rc<RefCell<Subscribers>>,
+    /// Set to stop the main loop, either by a signal or by an IPC request
+    pub should_exit: Arc<AtomicBool>,
+    /// Set when the configuration has been replaced outside of the file watcher, so that the
+    /// main loop applies it
+    pub config_changed: bool,
+    /// Number of IPC clients currently connected
+    pub ipc_clients: usize,
     pub xdg_dirs: BaseDirectories,
 }
 
@@ -71,17 +83,24 @@ impl Wpaperd {
             filelist_cache,
             image_loader,
             wallpaper_groups: Rc::new(RefCell::new(WallpaperGroups::new())),
+            subscribers: Rc::new(RefCell::new(Subscribers::new())),
+            should_exit: Arc::new(AtomicBool::new(false)),
+            config_changed: false,
+            ipc_clients: 0,
             xdg_dirs,
         })
     }
 
     pub fn update_surfaces(&mut self, ev_handle: LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
         for surface in &mut self.surfaces {
-            let res = self
+            surface.config_section = self
                 .config
-                .get_info_for_output(surface.name(), surface.description());
+                .matching_section(surface.display_info())
+                .to_string();
+            let res = self.config.get_info_for_output(surface.display_info());
             match res {
-                Ok(wallpaper_info) => {
+                Ok(mut wallpaper_info) => {
+                    surface.overrides.apply(&mut wallpaper_info);
                     surface.update_wallpaper_info(
                         &ev_handle,
                         qh,
@@ -97,6 +116,13 @@ impl Wpaperd {
         }
     }
 
+    /// Drop the overrides that should only last until the configuration is reloaded
+    pub fn drop_overrides_until_reload(&mut self) {
+        for surface in &mut self.surfaces {
+            surface.overrides.drop_until_reload();
+        }
+    }
+
     pub fn surface_from_name(&mut self, name: &str) -> Option<&mut Surface> {
         self.surfaces
             .iter_mut()
@@ -193,11 +219,6 @@ impl OutputHandler for Wpaperd {
             .as_ref()
             .map(|name| name.to_string())
             .unwrap_or_else(|| "unnamed".to_string());
-        let description = info
-            .description
-            .as_ref()
-            .map(|description| description.to_string())
-            .unwrap_or_else(|| "no-description".to_string());
         let display_info = DisplayInfo::new(info);
 
         let layer = self.layer_state.create_layer_surface(
@@ -233,7 +254,8 @@ impl OutputHandler for Wpaperd {
             }
         };
 
-        let wallpaper_info = match self.config.get_info_for_output(&name, &description) {
+        let config_section = self.config.matching_section(&display_info).to_string();
+        let wallpaper_info = match self.config.get_info_for_output(&display_info) {
             Ok(wallpaper_info) => wallpaper_info,
             Err(err) => {
                 warn!(
@@ -269,7 +291,13 @@ impl OutputHandler for Wpaperd {
             xdg_state_home_dir,
         );
         match res {
-            Ok(surface) => self.surfaces.push(surface),
+            Ok(mut surface) => {
+                surface.config_section = config_section;
+                self.surfaces.push(surface);
+                self.subscribers
+                    .borrow_mut()
+                    .broadcast(IpcEvent::DisplayAdded { monitor: name });
+            }
             Err(err) => error!(
                 "{:?}",
                 err.wrap_err(format!("Failed to create surface for display {name}"))
@@ -301,7 +329,12 @@ impl OutputHandler for Wpaperd {
             .find(|(_, surface)| *surface.wl_output() == output)
         {
             Some((index, _)) => {
-                self.surfaces.swap_remove(index);
+                let surface = self.surfaces.swap_remove(index);
+                self.subscribers
+                    .borrow_mut()
+                    .broadcast(IpcEvent::DisplayRemoved {
+                        monitor: surface.name().to_string(),
+                    });
             }
             None => {
                 // get name of display using xdg
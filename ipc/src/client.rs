@@ -0,0 +1,702 @@
+//! Client for the wpaperd IPC socket.
+
+use std::{
+    error::Error,
+    fmt, io,
+    io::ErrorKind,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use xdg::BaseDirectoriesError;
+
+use crate::{
+    read_frame, socket_path, write_frame, ConfigDiagnostic, DisplayDetails, DisplayResult,
+    DisplayStatus, GotoTarget, Handshake, ImagePosition, IpcError, IpcEvent, IpcMessage,
+    IpcResponse, WallpaperOverrides,
+};
+
+#[derive(Debug)]
+pub enum ClientError {
+    /// The path of the socket could not be found
+    SocketPath(BaseDirectoriesError),
+    /// The socket could not be reached, wpaperd is probably not running
+    Connect { path: PathBuf, source: io::Error },
+    /// The connection failed or timed out
+    Io(io::Error),
+    /// wpaperd closed the connection without answering
+    Disconnected,
+    /// The client and wpaperd speak different versions of the protocol
+    VersionMismatch { client: u32, daemon: u32 },
+    /// wpaperd could not execute the request
+    Daemon(IpcError),
+    /// wpaperd answered with a response that does not match the request
+    UnexpectedResponse,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::SocketPath(err) => write!(f, "could not locate the wpaperd socket: {err}"),
+            ClientError::Connect { path, source } => write!(
+                f,
+                "could not connect to wpaperd at {}: {source}",
+                path.to_string_lossy()
+            ),
+            ClientError::Io(err) => write!(f, "could not communicate with wpaperd: {err}"),
+            ClientError::Disconnected => write!(f, "wpaperd closed the connection"),
+            ClientError::VersionMismatch { client, daemon } => write!(
+                f,
+                "{}",
+                IpcError::VersionMismatch {
+                    client: *client,
+                    daemon: *daemon
+                }
+            ),
+            ClientError::Daemon(err) => write!(f, "{err}"),
+            ClientError::UnexpectedResponse => write!(f, "wpaperd sent an unexpected response"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::SocketPath(err) => Some(err),
+            ClientError::Connect { source, .. } => Some(source),
+            ClientError::Io(err) => Some(err),
+            ClientError::Daemon(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Io(err)
+    }
+}
+
+/// Version of a running wpaperd, returned by [`Client::version`]
+#[derive(Debug, Clone)]
+pub struct VersionInfo {
+    pub daemon: String,
+    pub protocol: u32,
+    /// Cargo features wpaperd has been built with
+    pub features: Vec<String>,
+}
+
+/// Check the answer of wpaperd to the handshake
+fn check_handshake(resp: Option<Result<Handshake, IpcError>>) -> Result<(), ClientError> {
+    match resp {
+        Some(Ok(_)) => Ok(()),
+        Some(Err(IpcError::VersionMismatch { client, daemon })) => {
+            Err(ClientError::VersionMismatch { client, daemon })
+        }
+        Some(Err(err)) => Err(ClientError::Daemon(err)),
+        None => Err(ClientError::Disconnected),
+    }
+}
+
+/// Return true if the error means that wpaperd closed a connection that was not being used.
+/// wpaperd drops idle connections without reading from them.
+fn is_stale_connection(err: &ClientError) -> bool {
+    match err {
+        ClientError::Disconnected => true,
+        ClientError::Io(err) => matches!(
+            err.kind(),
+            ErrorKind::BrokenPipe | ErrorKind::ConnectionReset | ErrorKind::UnexpectedEof
+        ),
+        _ => false,
+    }
+}
+
+/// Return true if `message` can be sent again when the connection breaks before the response
+/// arrives: wpaperd might have executed it already, so only the ones changing nothing qualify
+fn can_resend(message: &IpcMessage) -> bool {
+    matches!(
+        message,
+        IpcMessage::Ping
+            | IpcMessage::Version
+            | IpcMessage::CurrentWallpaper { .. }
+            | IpcMessage::AllWallpapers
+            | IpcMessage::GetStatus { .. }
+            | IpcMessage::ListDisplays { .. }
+            | IpcMessage::ListImages { .. }
+            | IpcMessage::GetHistory { .. }
+            | IpcMessage::Subscribe
+    )
+}
+
+/// Blocking connection to wpaperd.
+///
+/// The connection is kept open between requests; if wpaperd dropped it in the meantime, a new
+/// one is opened transparently.
+pub struct Client {
+    stream: UnixStream,
+    path: PathBuf,
+    timeout: Duration,
+    /// True once a request has been answered on this connection
+    used: bool,
+}
+
+impl Client {
+    /// How long a request can take before failing
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Connect to the wpaperd running in this session
+    pub fn connect() -> Result<Self, ClientError> {
+        let path = socket_path().map_err(ClientError::SocketPath)?;
+        Self::connect_to(&path, Self::DEFAULT_TIMEOUT)
+    }
+
+    /// Connect to the socket at `path`, every read and write will fail after `timeout`
+    pub fn connect_to(path: &Path, timeout: Duration) -> Result<Self, ClientError> {
+        Ok(Self {
+            stream: Self::open(path, timeout)?,
+            path: path.to_path_buf(),
+            timeout,
+            used: false,
+        })
+    }
+
+    fn open(path: &Path, timeout: Duration) -> Result<UnixStream, ClientError> {
+        let mut stream = UnixStream::connect(path).map_err(|source| ClientError::Connect {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        write_frame(&mut stream, &Handshake::new())?;
+        check_handshake(read_frame(&mut stream)?)?;
+        Ok(stream)
+    }
+
+    /// Send a message and wait for the response of wpaperd
+    pub fn request(&mut self, message: &IpcMessage) -> Result<IpcResponse, ClientError> {
+        match write_frame(&mut self.stream, message).map_err(ClientError::from) {
+            // Nothing has been sent, the connection was closed while idle
+            Err(err) if self.used && is_stale_connection(&err) => {}
+            Err(err) => return Err(err),
+            Ok(()) => match self.receive() {
+                Err(err) if self.used && is_stale_connection(&err) && can_resend(message) => {}
+                res => return res,
+            },
+        }
+        self.stream = Self::open(&self.path, self.timeout)?;
+        write_frame(&mut self.stream, message)?;
+        self.receive()
+    }
+
+    fn receive(&mut self) -> Result<IpcResponse, ClientError> {
+        let resp: Result<IpcResponse, IpcError> =
+            read_frame(&mut self.stream)?.ok_or(ClientError::Disconnected)?;
+        self.used = true;
+        resp.map_err(ClientError::Daemon)
+    }
+
+    /// Receive the wpaperd events from now on
+    pub fn subscribe(mut self) -> Result<Subscription, ClientError> {
+        match self.request(&IpcMessage::Subscribe)? {
+            IpcResponse::Subscribed => {
+                // Events can take any time to come
+                self.stream.set_read_timeout(None)?;
+                Ok(Subscription {
+                    stream: self.stream,
+                })
+            }
+            _ => Err(ClientError::UnexpectedResponse),
+        }
+    }
+}
+
+/// Events sent by wpaperd after [`Client::subscribe`], the iterator ends when wpaperd exits
+pub struct Subscription {
+    stream: UnixStream,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<IpcEvent, ClientError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        read_frame(&mut self.stream)
+            .map_err(ClientError::Io)
+            .transpose()
+    }
+}
+
+/// Define the typed requests on both the blocking and the async client
+macro_rules! requests {
+    ($($(#[$attr:meta])* fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty {
+        $message:expr, $resp:pat => $value:expr
+    })*) => {
+        impl Client {
+            $(
+                $(#[$attr])*
+                pub fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, ClientError> {
+                    match self.request(&$message)? {
+                        $resp => Ok($value),
+                        _ => Err(ClientError::UnexpectedResponse),
+                    }
+                }
+            )*
+        }
+
+        #[cfg(feature = "async")]
+        impl AsyncClient {
+            $(
+                $(#[$attr])*
+                pub async fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, ClientError> {
+                    match self.request(&$message).await? {
+                        $resp => Ok($value),
+                        _ => Err(ClientError::UnexpectedResponse),
+                    }
+                }
+            )*
+        }
+    };
+}
+
+// An empty list of monitors means all of them. The commands changing the wallpaper return the
+// result for each display, as some of them might fail while the others succeed.
+requests! {
+    /// Check that wpaperd is answering
+    fn ping() -> () {
+        IpcMessage::Ping, IpcResponse::Pong => ()
+    }
+    fn version() -> VersionInfo {
+        IpcMessage::Version,
+        IpcResponse::Version { daemon, protocol, features } => VersionInfo {
+            daemon,
+            protocol,
+            features,
+        }
+    }
+    /// Stop wpaperd
+    fn shutdown() -> () {
+        IpcMessage::Shutdown, IpcResponse::Ok => ()
+    }
+    fn current_wallpaper(monitor: &str) -> PathBuf {
+        IpcMessage::CurrentWallpaper { monitor: monitor.to_string() },
+        IpcResponse::CurrentWallpaper { path } => path
+    }
+    /// Current wallpaper of each display
+    fn all_wallpapers() -> Vec<(String, PathBuf)> {
+        IpcMessage::AllWallpapers, IpcResponse::AllWallpapers { entries } => entries
+    }
+    fn next(monitors: Vec<String>) -> Vec<DisplayResult> {
+        IpcMessage::NextWallpaper { monitors },
+        IpcResponse::DisplayResults { results } => results
+    }
+    fn previous(monitors: Vec<String>) -> Vec<DisplayResult> {
+        IpcMessage::PreviousWallpaper { monitors },
+        IpcResponse::DisplayResults { results } => results
+    }
+    fn pause(monitors: Vec<String>) -> () {
+        IpcMessage::PauseWallpaper { monitors }, IpcResponse::Ok => ()
+    }
+    fn resume(monitors: Vec<String>) -> () {
+        IpcMessage::ResumeWallpaper { monitors }, IpcResponse::Ok => ()
+    }
+    fn toggle_pause(monitors: Vec<String>) -> () {
+        IpcMessage::TogglePauseWallpaper { monitors }, IpcResponse::Ok => ()
+    }
+    fn reload_wallpaper(monitors: Vec<String>) -> Vec<DisplayResult> {
+        IpcMessage::ReloadWallpaper { monitors },
+        IpcResponse::DisplayResults { results } => results
+    }
+    fn status(monitors: Vec<String>) -> Vec<DisplayStatus> {
+        IpcMessage::GetStatus { monitors }, IpcResponse::DisplaysStatus { entries } => entries
+    }
+    /// See [`IpcMessage::SetWallpaper`]
+    fn set_wallpaper(monitors: Vec<String>, path: PathBuf, sticky: bool) -> Vec<DisplayResult> {
+        IpcMessage::SetWallpaper { monitors, path, sticky },
+        IpcResponse::DisplayResults { results } => results
+    }
+    fn unset_wallpaper(monitors: Vec<String>) -> Vec<DisplayResult> {
+        IpcMessage::UnsetWallpaper { monitors },
+        IpcResponse::DisplayResults { results } => results
+    }
+    /// See [`IpcMessage::SetOverrides`]
+    fn set_overrides(
+        monitors: Vec<String>,
+        overrides: WallpaperOverrides,
+        until_reload: bool
+    ) -> () {
+        IpcMessage::SetOverrides { monitors, overrides, until_reload }, IpcResponse::Ok => ()
+    }
+    fn clear_overrides(monitors: Vec<String>) -> () {
+        IpcMessage::ClearOverrides { monitors }, IpcResponse::Ok => ()
+    }
+    fn list_displays(monitors: Vec<String>) -> Vec<DisplayDetails> {
+        IpcMessage::ListDisplays { monitors }, IpcResponse::Displays { displays } => displays
+    }
+    fn list_images(monitor: &str) -> (Vec<PathBuf>, ImagePosition) {
+        IpcMessage::ListImages { monitor: monitor.to_string() },
+        IpcResponse::Images { files, position } => (files, position)
+    }
+    /// Images drawn recently, from the oldest to the newest, and the index of the current one
+    fn history(monitor: &str) -> (Vec<PathBuf>, usize) {
+        IpcMessage::GetHistory { monitor: monitor.to_string() },
+        IpcResponse::History { entries, current } => (entries, current)
+    }
+    fn jump_to_history(monitor: &str, index: usize) -> Vec<DisplayResult> {
+        IpcMessage::JumpToHistory { monitor: monitor.to_string(), index },
+        IpcResponse::DisplayResults { results } => results
+    }
+    fn goto_image(monitors: Vec<String>, target: GotoTarget) -> Vec<DisplayResult> {
+        IpcMessage::GotoImage { monitors, target },
+        IpcResponse::DisplayResults { results } => results
+    }
+    /// Reload the configuration, from `path` if set, and return the errors and the warnings
+    /// found in its sections
+    fn reload_config(path: Option<PathBuf>) -> (Vec<ConfigDiagnostic>, Vec<ConfigDiagnostic>) {
+        IpcMessage::ReloadConfig { path },
+        IpcResponse::ConfigReloaded { errors, warnings } => (errors, warnings)
+    }
+}
+
+#[cfg(feature = "async")]
+mod nonblocking {
+    use std::{
+        io::{self, ErrorKind},
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use serde::{de::DeserializeOwned, Serialize};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+        time::timeout,
+    };
+
+    use super::{can_resend, check_handshake, is_stale_connection, Client, ClientError};
+    use crate::{
+        encode_frame, frame_len, socket_path, Handshake, IpcError, IpcEvent, IpcMessage,
+        IpcResponse,
+    };
+
+    fn timed_out() -> ClientError {
+        ClientError::Io(ErrorKind::TimedOut.into())
+    }
+
+    async fn write_frame<T: Serialize>(stream: &mut UnixStream, value: &T) -> io::Result<()> {
+        stream.write_all(&encode_frame(value)?).await
+    }
+
+    async fn read_frame<T: DeserializeOwned>(stream: &mut UnixStream) -> io::Result<Option<T>> {
+        let mut header = [0; 4];
+        match stream.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let mut payload = vec![0; frame_len(header)?];
+        stream.read_exact(&mut payload).await?;
+        serde_json::from_slice(&payload)
+            .map(Some)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+    }
+
+    /// Async version of [`Client`], based on tokio
+    pub struct AsyncClient {
+        stream: UnixStream,
+        path: PathBuf,
+        timeout: Duration,
+        used: bool,
+    }
+
+    impl AsyncClient {
+        /// Connect to the wpaperd running in this session
+        pub async fn connect() -> Result<Self, ClientError> {
+            let path = socket_path().map_err(ClientError::SocketPath)?;
+            Self::connect_to(&path, Client::DEFAULT_TIMEOUT).await
+        }
+
+        /// Connect to the socket at `path`, every request will fail after `timeout`
+        pub async fn connect_to(path: &Path, timeout: Duration) -> Result<Self, ClientError> {
+            Ok(Self {
+                stream: Self::open(path, timeout).await?,
+                path: path.to_path_buf(),
+                timeout,
+                used: false,
+            })
+        }
+
+        async fn open(path: &Path, duration: Duration) -> Result<UnixStream, ClientError> {
+            let connect = async {
+                let mut stream =
+                    UnixStream::connect(path)
+                        .await
+                        .map_err(|source| ClientError::Connect {
+                            path: path.to_path_buf(),
+                            source,
+                        })?;
+                write_frame(&mut stream, &Handshake::new()).await?;
+                check_handshake(read_frame(&mut stream).await?)?;
+                Ok::<_, ClientError>(stream)
+            };
+            timeout(duration, connect).await.map_err(|_| timed_out())?
+        }
+
+        /// Send a message and wait for the response of wpaperd
+        pub async fn request(&mut self, message: &IpcMessage) -> Result<IpcResponse, ClientError> {
+            match self.send(message).await {
+                // Nothing has been sent, the connection was closed while idle
+                Err(err) if self.used && is_stale_connection(&err) => {}
+                Err(err) => return Err(err),
+                Ok(()) => match self.receive().await {
+                    Err(err) if self.used && is_stale_connection(&err) && can_resend(message) => {}
+                    res => return res,
+                },
+            }
+            self.stream = Self::open(&self.path, self.timeout).await?;
+            self.send(message).await?;
+            self.receive().await
+        }
+
+        async fn send(&mut self, message: &IpcMessage) -> Result<(), ClientError> {
+            timeout(self.timeout, write_frame(&mut self.stream, message))
+                .await
+                .map_err(|_| timed_out())?
+                .map_err(ClientError::from)
+        }
+
+        async fn receive(&mut self) -> Result<IpcResponse, ClientError> {
+            let resp = timeout(
+                self.timeout,
+                read_frame::<Result<IpcResponse, IpcError>>(&mut self.stream),
+            )
+            .await
+            .map_err(|_| timed_out())??
+            .ok_or(ClientError::Disconnected)?;
+            self.used = true;
+            resp.map_err(ClientError::Daemon)
+        }
+
+        /// Receive the wpaperd events from now on
+        pub async fn subscribe(mut self) -> Result<AsyncSubscription, ClientError> {
+            match self.request(&IpcMessage::Subscribe).await? {
+                IpcResponse::Subscribed => Ok(AsyncSubscription {
+                    stream: self.stream,
+                }),
+                _ => Err(ClientError::UnexpectedResponse),
+            }
+        }
+    }
+
+    /// Events sent by wpaperd after [`AsyncClient::subscribe`]
+    pub struct AsyncSubscription {
+        stream: UnixStream,
+    }
+
+    impl AsyncSubscription {
+        /// Wait for the next event, `None` is returned when wpaperd exits
+        pub async fn next_event(&mut self) -> Result<Option<IpcEvent>, ClientError> {
+            Ok(read_frame(&mut self.stream).await?)
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub use nonblocking::{AsyncClient, AsyncSubscription};
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        os::unix::net::UnixListener,
+        sync::mpsc,
+        thread::{self, JoinHandle},
+    };
+
+    use super::*;
+    use crate::PROTOCOL_VERSION;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Listen on a new socket, removed when the test ends
+    struct Socket {
+        path: PathBuf,
+        listener: UnixListener,
+    }
+
+    impl Socket {
+        fn bind(name: &str) -> Self {
+            let path = env::temp_dir().join(format!(
+                "wpaperd-client-test-{}-{name}.sock",
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).unwrap();
+            Self { path, listener }
+        }
+
+        /// Serve the connections on a thread, like wpaperd would
+        fn serve<F>(self, handler: F) -> (PathBuf, JoinHandle<()>)
+        where
+            F: FnOnce(&UnixListener) + Send + 'static,
+        {
+            let path = self.path.clone();
+            let thread = thread::spawn(move || handler(&self.listener));
+            (path, thread)
+        }
+    }
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Accept a connection and answer its handshake
+    fn accept(listener: &UnixListener) -> UnixStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        let handshake: Handshake = read_frame(&mut stream).unwrap().unwrap();
+        assert_eq!(handshake.version, PROTOCOL_VERSION);
+        write_frame(&mut stream, &Ok::<_, IpcError>(Handshake::new())).unwrap();
+        stream
+    }
+
+    fn receive(stream: &mut UnixStream) -> IpcMessage {
+        read_frame(stream).unwrap().unwrap()
+    }
+
+    fn answer(stream: &mut UnixStream, resp: IpcResponse) {
+        write_frame(stream, &Ok::<_, IpcError>(resp)).unwrap();
+    }
+
+    #[test]
+    fn test_handshake() {
+        let (path, server) = Socket::bind("handshake").serve(|listener| {
+            let mut stream = accept(listener);
+            assert!(matches!(receive(&mut stream), IpcMessage::Ping));
+            answer(&mut stream, IpcResponse::Pong);
+            assert!(matches!(
+                receive(&mut stream),
+                IpcMessage::CurrentWallpaper { monitor } if monitor == "DP-1"
+            ));
+            write_frame(
+                &mut stream,
+                &Err::<IpcResponse, _>(IpcError::MonitorNotFound {
+                    monitor: "DP-1".to_string(),
+                }),
+            )
+            .unwrap();
+        });
+
+        let mut client = Client::connect_to(&path, TIMEOUT).unwrap();
+        client.ping().unwrap();
+        assert!(matches!(
+            client.current_wallpaper("DP-1"),
+            Err(ClientError::Daemon(IpcError::MonitorNotFound { .. }))
+        ));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let (path, server) = Socket::bind("version").serve(|listener| {
+            let (mut stream, _) = listener.accept().unwrap();
+            let handshake: Handshake = read_frame(&mut stream).unwrap().unwrap();
+            write_frame(
+                &mut stream,
+                &Err::<Handshake, _>(IpcError::VersionMismatch {
+                    client: handshake.version,
+                    daemon: PROTOCOL_VERSION + 1,
+                }),
+            )
+            .unwrap();
+        });
+
+        match Client::connect_to(&path, TIMEOUT) {
+            Err(ClientError::VersionMismatch { client, daemon }) => {
+                assert_eq!(client, PROTOCOL_VERSION);
+                assert_eq!(daemon, PROTOCOL_VERSION + 1);
+            }
+            res => panic!("expected a version mismatch, got {:?}", res.err()),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (done, wait) = mpsc::channel::<()>();
+        let (path, server) = Socket::bind("timeout").serve(move |listener| {
+            let mut stream = accept(listener);
+            receive(&mut stream);
+            // Never answer, but keep the connection open until the client gave up
+            let _ = wait.recv();
+        });
+
+        let mut client = Client::connect_to(&path, Duration::from_millis(100)).unwrap();
+        match client.ping() {
+            Err(ClientError::Io(err)) => assert!(
+                matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+                "{err}"
+            ),
+            res => panic!("expected a timeout, got {res:?}"),
+        }
+        done.send(()).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_retry_stale_connection() {
+        let (path, server) = Socket::bind("retry").serve(|listener| {
+            let mut stream = accept(listener);
+            receive(&mut stream);
+            answer(&mut stream, IpcResponse::Pong);
+            // Close the connection while it is idle, as wpaperd does after IPC_TIMEOUT
+            drop(stream);
+
+            let mut stream = accept(listener);
+            assert!(matches!(receive(&mut stream), IpcMessage::Ping));
+            answer(&mut stream, IpcResponse::Pong);
+        });
+
+        let mut client = Client::connect_to(&path, TIMEOUT).unwrap();
+        client.ping().unwrap();
+        // Let the server close the connection
+        thread::sleep(Duration::from_millis(100));
+        client.ping().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_retry_after_sending() {
+        let (path, server) = Socket::bind("resend").serve(|listener| {
+            let mut stream = accept(listener);
+            receive(&mut stream);
+            answer(&mut stream, IpcResponse::Pong);
+            // The message has been read, it might have been executed already
+            assert!(matches!(
+                receive(&mut stream),
+                IpcMessage::NextWallpaper { .. }
+            ));
+            drop(stream);
+
+            let mut stream = accept(listener);
+            receive(&mut stream);
+            answer(&mut stream, IpcResponse::Pong);
+            assert!(matches!(receive(&mut stream), IpcMessage::Ping));
+            drop(stream);
+            // Read-only messages are sent again
+            let mut stream = accept(listener);
+            assert!(matches!(receive(&mut stream), IpcMessage::Ping));
+            answer(&mut stream, IpcResponse::Pong);
+        });
+
+        let mut client = Client::connect_to(&path, TIMEOUT).unwrap();
+        client.ping().unwrap();
+        assert!(matches!(
+            client.next(Vec::new()),
+            Err(ClientError::Disconnected)
+        ));
+        client.ping().unwrap();
+        client.ping().unwrap();
+        server.join().unwrap();
+    }
+}
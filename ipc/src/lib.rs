@@ -1,19 +1,266 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    env, fmt,
+    io::{self, ErrorKind, Read, Write},
+    path::PathBuf,
+    time::Duration,
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use xdg::{BaseDirectories, BaseDirectoriesError};
 
+mod client;
+
+#[cfg(feature = "async")]
+pub use client::{AsyncClient, AsyncSubscription};
+pub use client::{Client, ClientError, Subscription, VersionInfo};
+
+/// Version of the protocol spoken over the IPC socket.
+/// It is exchanged in the handshake and both sides must agree on it.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Maximum size of the payload of a single frame
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// First frame sent by the client on every connection. The daemon answers with a
+/// `Result<Handshake, IpcError>` containing its own version.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Handshake {
+    pub version: u32,
+}
+
+impl Handshake {
+    pub fn new() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl Default for Handshake {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum IpcMessage {
-    CurrentWallpaper { monitor: String },
-    NextWallpaper { monitors: Vec<String> },
-    PreviousWallpaper { monitors: Vec<String> },
-    PauseWallpaper { monitors: Vec<String> },
-    ResumeWallpaper { monitors: Vec<String> },
-    TogglePauseWallpaper { monitors: Vec<String> },
+    /// Check that the daemon is running and answering, replied with [`IpcResponse::Pong`]
+    Ping,
+    /// Get the version of the daemon, of its protocol and its enabled features
+    Version,
+    /// Stop the daemon; the reply is sent before exiting
+    Shutdown,
+    CurrentWallpaper {
+        monitor: String,
+    },
+    NextWallpaper {
+        monitors: Vec<String>,
+    },
+    PreviousWallpaper {
+        monitors: Vec<String>,
+    },
+    PauseWallpaper {
+        monitors: Vec<String>,
+    },
+    ResumeWallpaper {
+        monitors: Vec<String>,
+    },
+    TogglePauseWallpaper {
+        monitors: Vec<String>,
+    },
     AllWallpapers,
-    ReloadWallpaper { monitors: Vec<String> },
-    GetStatus { monitors: Vec<String> },
+    ReloadWallpaper {
+        monitors: Vec<String>,
+    },
+    GetStatus {
+        monitors: Vec<String>,
+    },
+    /// Keep the connection open and receive an [`IpcEvent`] frame every time something changes
+    Subscribe,
+    /// Show the image at `path`. When `sticky` is false the wallpaper keeps changing as usual,
+    /// otherwise the image stays on screen until [`IpcMessage::UnsetWallpaper`] is sent.
+    SetWallpaper {
+        monitors: Vec<String>,
+        path: PathBuf,
+        sticky: bool,
+    },
+    /// Go back to the configured path after [`IpcMessage::SetWallpaper`] with `sticky` set
+    UnsetWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Change the settings of the displays at runtime. The values that are not set in
+    /// `overrides` are kept. When `until_reload` is true, the values set by this message are
+    /// dropped the next time the configuration is reloaded.
+    SetOverrides {
+        monitors: Vec<String>,
+        overrides: WallpaperOverrides,
+        until_reload: bool,
+    },
+    /// Go back to the configured settings
+    ClearOverrides {
+        monitors: Vec<String>,
+    },
+    /// Get the geometry, the resolved configuration and the state of the displays
+    ListDisplays {
+        monitors: Vec<String>,
+    },
+    /// Get the images available for a display and the position of the current one
+    ListImages {
+        monitor: String,
+    },
+    /// Get the images drawn recently on a display (only kept with `random` sorting)
+    GetHistory {
+        monitor: String,
+    },
+    /// Show again the image at `index` of the history returned by [`IpcMessage::GetHistory`]
+    JumpToHistory {
+        monitor: String,
+        index: usize,
+    },
+    /// Show an image of the configured directory; the sorting continues from there
+    GotoImage {
+        monitors: Vec<String>,
+        target: GotoTarget,
+    },
+    /// Read the configuration again, from `path` if set, and apply it.
+    /// The problems found in the file are returned in [`IpcResponse::ConfigReloaded`].
+    /// Only the user running wpaperd can set `path`.
+    ReloadConfig {
+        path: Option<PathBuf>,
+    },
+}
+
+/// Image to show with [`IpcMessage::GotoImage`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum GotoTarget {
+    /// Index in the list of files returned by [`IpcMessage::ListImages`]
+    Index(usize),
+    /// File name of the image; when no file has this exact name, the first one whose path
+    /// contains it is used (e.g. `nature/lake`)
+    Name(String),
+}
+
+impl fmt::Display for GotoTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GotoTarget::Index(index) => write!(f, "index {index}"),
+            GotoTarget::Name(name) => write!(f, "name {name:?}"),
+        }
+    }
+}
+
+/// Where the image picker of a display is, returned by [`IpcMessage::ListImages`]
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ImagePosition {
+    /// Index of the current image in the list of files (`ascending` and `descending` sorting)
+    Index(usize),
+    /// Images drawn so far and the index of the current one (`random` sorting)
+    Queue {
+        entries: Vec<PathBuf>,
+        current: usize,
+    },
+    /// The path is not a directory
+    Static,
+}
+
+/// Settings of a display that can be changed at runtime, on top of its configuration.
+/// The values use the same format as the configuration file, `None` means that the configured
+/// value is used.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct WallpaperOverrides {
+    pub duration: Option<Duration>,
+    pub mode: Option<String>,
+    pub offset: Option<f32>,
+    pub transition: Option<String>,
+    pub sorting: Option<String>,
+}
+
+impl WallpaperOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Output geometry and state of a display, returned by [`IpcMessage::ListDisplays`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DisplayDetails {
+    pub name: String,
+    pub description: String,
+    pub make: String,
+    pub model: String,
+    /// Serial number, empty when the compositor does not report it
+    pub serial: String,
+    pub width: i32,
+    pub height: i32,
+    pub scale: i32,
+    pub transform: String,
+    /// Section of the configuration file matching this display, `any` if none does
+    pub section: String,
+    /// Configuration in use for this display, including the overrides
+    pub wallpaper: WallpaperDetails,
+    pub overrides: WallpaperOverrides,
+    pub current_image: PathBuf,
+    pub paused: bool,
+    /// False when the EGL context became invalid and has not been recreated yet
+    pub has_context: bool,
+}
+
+/// Outcome of a command for a single display, returned by [`IpcResponse::DisplayResults`]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DisplayResult {
+    pub display: String,
+    /// Why the wallpaper could not be changed, `None` when the new image started loading.
+    /// The images are decoded in the background, the ones that fail afterwards are reported
+    /// with [`IpcEvent::ImageLoadFailed`].
+    pub error: Option<String>,
+}
+
+impl DisplayResult {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Configuration resolved for a display, with the same format as the configuration file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WallpaperDetails {
+    pub path: Vec<WallpaperSource>,
+    pub mode: String,
+    pub sorting: Option<String>,
+    pub duration: Option<Duration>,
+    pub transition: String,
+    pub transition_time: u32,
+    pub group: Option<u8>,
+    pub offset: Option<f32>,
+    pub queue_size: usize,
+    pub recursive: bool,
+    /// Globs selecting the images of the directories
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub exec: Option<PathBuf>,
+}
+
+/// One of the images or directories the wallpapers of a display are picked from
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WallpaperSource {
+    pub path: PathBuf,
+    /// How likely its images are to be picked by the random sortings
+    pub weight: u32,
+}
+
+/// Problem found in a section of the configuration file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConfigDiagnostic {
+    pub section: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DisplayStatus {
+    pub display: String,
+    pub status: String,
+    pub duration_left: Option<Duration>,
+    pub overrides: WallpaperOverrides,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -25,18 +272,305 @@ pub enum IpcResponse {
         entries: Vec<(String, PathBuf)>,
     },
     DisplaysStatus {
-        entries: Vec<(String, String, Option<Duration>)>,
+        entries: Vec<DisplayStatus>,
+    },
+    Displays {
+        displays: Vec<DisplayDetails>,
+    },
+    Images {
+        files: Vec<PathBuf>,
+        position: ImagePosition,
+    },
+    /// Images drawn so far, from the oldest to the newest, and the index of the current one
+    History {
+        entries: Vec<PathBuf>,
+        current: usize,
     },
+    /// Result for each display targeted by a command changing the wallpaper; a display can fail
+    /// while the others succeed
+    DisplayResults {
+        results: Vec<DisplayResult>,
+    },
+    Pong,
+    Version {
+        daemon: String,
+        protocol: u32,
+        /// Cargo features the daemon has been built with
+        features: Vec<String>,
+    },
+    /// The configuration has been applied. The sections listed in `errors` are not valid and
+    /// have been ignored.
+    ConfigReloaded {
+        errors: Vec<ConfigDiagnostic>,
+        warnings: Vec<ConfigDiagnostic>,
+    },
+    /// The connection has been subscribed, only [`IpcEvent`] frames will follow
+    Subscribed,
     Ok,
 }
 
+/// Events pushed to the clients that sent [`IpcMessage::Subscribe`]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum IpcEvent {
+    WallpaperChanged { monitor: String, path: PathBuf },
+    Paused { monitor: String },
+    Resumed { monitor: String },
+    DisplayAdded { monitor: String },
+    DisplayRemoved { monitor: String },
+    ConfigReloaded,
+    ImageLoadFailed { monitor: String, path: PathBuf },
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum IpcError {
     MonitorNotFound { monitor: String },
-    DrawErrors(Vec<(String, String)>),
+    ImageNotFound { path: PathBuf },
+    NotAnImage { path: PathBuf },
+    InvalidOverride { reason: String },
+    NoHistory { monitor: String },
+    InvalidHistoryIndex { index: usize, len: usize },
+    NoMatchingImage { monitor: String, target: GotoTarget },
+    InvalidConfig { reason: String },
+    VersionMismatch { client: u32, daemon: u32 },
+    PermissionDenied { reason: String },
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::MonitorNotFound { monitor } => {
+                write!(f, "monitor {monitor} could not be found")
+            }
+            IpcError::ImageNotFound { path } => {
+                write!(f, "image {} could not be found", path.to_string_lossy())
+            }
+            IpcError::NotAnImage { path } => {
+                write!(f, "{} is not an image", path.to_string_lossy())
+            }
+            IpcError::InvalidOverride { reason } => write!(f, "invalid override: {reason}"),
+            IpcError::NoHistory { monitor } => write!(
+                f,
+                "monitor {monitor} does not keep an history, its sorting is not random"
+            ),
+            IpcError::InvalidHistoryIndex { index, len } => write!(
+                f,
+                "index {index} is out of the history, which has {len} entries"
+            ),
+            IpcError::NoMatchingImage { monitor, target } => write!(
+                f,
+                "no image matching {target} could be found for monitor {monitor}"
+            ),
+            IpcError::InvalidConfig { reason } => {
+                write!(f, "the configuration could not be read: {reason}")
+            }
+            IpcError::VersionMismatch { client, daemon } => write!(
+                f,
+                "the client uses protocol version {client} but wpaperd uses version {daemon}, \
+                make sure that both have been updated"
+            ),
+            IpcError::PermissionDenied { reason } => write!(f, "permission denied: {reason}"),
+        }
+    }
 }
 
+impl std::error::Error for IpcError {}
+
+/// Name of the wpaperd instance for the Wayland display of the environment, taken from
+/// `WAYLAND_DISPLAY` (`wayland-0` when it is not set)
+pub fn current_instance() -> String {
+    env::var_os("WAYLAND_DISPLAY")
+        // WAYLAND_DISPLAY can also be the absolute path of the compositor socket
+        .and_then(|display| {
+            PathBuf::from(display)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "wayland-0".to_string())
+}
+
+/// Path of the socket of the wpaperd instance running on the current Wayland display
 pub fn socket_path() -> Result<PathBuf, BaseDirectoriesError> {
+    instance_socket_path(&current_instance())
+}
+
+/// Path of the socket of the wpaperd instance running on the Wayland display `instance`, so
+/// that multiple instances (e.g. in a nested compositor) do not clobber each other
+pub fn instance_socket_path(instance: &str) -> Result<PathBuf, BaseDirectoriesError> {
     let xdg_dirs = BaseDirectories::with_prefix("wpaperd")?;
-    Ok(xdg_dirs.get_runtime_directory()?.join("wpaperd.sock"))
+    Ok(xdg_dirs
+        .get_runtime_directory()?
+        .join(format!("wpaperd-{instance}.sock")))
+}
+
+/// Write a single frame: the length of the payload as a big-endian u32, followed by the
+/// payload encoded as json.
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, value: &T) -> io::Result<()> {
+    writer.write_all(&encode_frame(value)?)?;
+    writer.flush()
+}
+
+/// Encode a frame, header included
+fn encode_frame<T: Serialize>(value: &T) -> io::Result<Vec<u8>> {
+    let payload =
+        serde_json::to_vec(value).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "frame of {} bytes exceeds the maximum size of {MAX_FRAME_SIZE} bytes",
+                payload.len()
+            ),
+        ));
+    }
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Read a single frame written by [`write_frame`].
+/// Returns `None` when the other side closed the connection before sending a new frame.
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> io::Result<Option<T>> {
+    let mut header = [0; 4];
+    let mut read = 0;
+    while read < header.len() {
+        match reader.read(&mut header[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    let len = frame_len(header)?;
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    serde_json::from_slice(&payload)
+        .map(Some)
+        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+/// Length of the payload announced by a frame header
+fn frame_len(header: [u8; 4]) -> io::Result<usize> {
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("frame of {len} bytes exceeds the maximum size of {MAX_FRAME_SIZE} bytes"),
+        ));
+    }
+    Ok(len)
+}
+
+/// Incremental reader for the frames written by [`write_frame`], meant for non-blocking
+/// streams: push the bytes as they arrive and take the frames once they are complete.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Decode the next frame, or return `None` if it has not been received entirely yet
+    pub fn next_frame<T: DeserializeOwned>(&mut self) -> io::Result<Option<T>> {
+        let Some(header) = self.buffer.get(..4) else {
+            return Ok(None);
+        };
+        let len = frame_len(header.try_into().expect("header to be 4 bytes long"))?;
+        let Some(payload) = self.buffer.get(4..4 + len) else {
+            return Ok(None);
+        };
+        let value = serde_json::from_slice(payload)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        self.buffer.drain(..4 + len);
+        Ok(Some(value))
+    }
+
+    /// True when part of a frame has been received but not decoded yet
+    pub fn has_pending_data(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Handshake::new()).unwrap();
+        write_frame(
+            &mut buf,
+            &IpcMessage::NextWallpaper {
+                monitors: vec!["DP-1".to_string()],
+            },
+        )
+        .unwrap();
+
+        let mut reader = buf.as_slice();
+        let handshake: Handshake = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(handshake.version, PROTOCOL_VERSION);
+        let message: IpcMessage = read_frame(&mut reader).unwrap().unwrap();
+        assert!(matches!(message, IpcMessage::NextWallpaper { monitors } if monitors == ["DP-1"]));
+        assert!(read_frame::<_, IpcMessage>(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_frame_decoder() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Handshake::new()).unwrap();
+        write_frame(&mut buf, &IpcMessage::Ping).unwrap();
+
+        // Feed the decoder one byte at a time, like a slow client would
+        let mut decoder = FrameDecoder::new();
+        let mut handshake = None;
+        let mut bytes = buf.iter();
+        while handshake.is_none() {
+            decoder.push(&[*bytes.next().unwrap()]);
+            handshake = decoder.next_frame::<Handshake>().unwrap();
+        }
+        assert_eq!(handshake.unwrap().version, PROTOCOL_VERSION);
+        assert!(!decoder.has_pending_data());
+
+        decoder.push(bytes.as_slice());
+        let message: IpcMessage = decoder.next_frame().unwrap().unwrap();
+        assert!(matches!(message, IpcMessage::Ping));
+        assert!(decoder.next_frame::<IpcMessage>().unwrap().is_none());
+        assert!(!decoder.has_pending_data());
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &Handshake::new()).unwrap();
+        buf.truncate(buf.len() - 1);
+        let err = read_frame::<_, Handshake>(&mut buf.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_instance_socket_path() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let runtime_dir = env::temp_dir().join(format!("wpaperd-ipc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&runtime_dir).unwrap();
+        std::fs::set_permissions(&runtime_dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+        env::set_var("XDG_RUNTIME_DIR", &runtime_dir);
+
+        // The socket lives directly in the runtime directory
+        assert_eq!(
+            instance_socket_path("wayland-1").unwrap(),
+            runtime_dir.join("wpaperd-wayland-1.sock")
+        );
+        std::fs::remove_dir(&runtime_dir).unwrap();
+    }
 }